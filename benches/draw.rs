@@ -0,0 +1,80 @@
+// Compares the two ways the game can draw its padles, balls and sparks: every
+// one of them as a `Quad` of its own, the way the game used to do it, and all
+// of them pushed into one `InstanceArray` that is drawn at once, the way it does
+// now. Both go through the game's own `draw`, on a match that has been played
+// for a while with everything switched on that adds pieces: three big balls
+// with their ghosts, two padles a side and outlines around all of them. It
+// opens a window just like the game, so it needs a display and a sound device
+// to run:
+//
+//     cargo bench --bench draw
+
+use std::time::{Duration, Instant};
+
+use ggez::{conf, event::EventHandler, Context, ContextBuilder, GameResult};
+use moving_paddle::{config::Config, keybindings::Key, GameState};
+
+// A board this many cells across and down, each this many pixels wide, about
+// fills a large screen.
+const GRID_SIZE: (i16, i16) = (240, 135);
+const CELL_SIZE: i16 = 8;
+
+// How many updates the match is played for before anything is drawn, so the
+// balls are on their way and sparks are flying.
+const UPDATES: u32 = 600;
+
+// How many frames each way is drawn for, after a few to warm up.
+const WARM_UP_FRAMES: u32 = 10;
+const FRAMES: u32 = 200;
+
+fn main() -> GameResult {
+    let config = Config {
+        grid_size: GRID_SIZE,
+        cell_size: (CELL_SIZE, CELL_SIZE),
+        ball_count: 3,
+        ball_size: 3,
+        team_size: 2,
+        outlines: true,
+        ghost: Some(30),
+        ..Config::default()
+    };
+    let (width, height) = config.screen_size();
+    let (mut ctx, _events) = ContextBuilder::new("moving_paddles_draw_bench", "moving_paddles")
+        .window_setup(conf::WindowSetup::default().title("Drawing benchmark").vsync(false))
+        .window_mode(conf::WindowMode::default().dimensions(width, height))
+        .build()?;
+
+    // A one player match, where the computer keeps the rallies going
+    let mut state = GameState::with_seed(1, config);
+    state.key_down(Key::Return);
+    for _ in 0..UPDATES {
+        state.step();
+    }
+
+    state.draw_one_quad_per_piece(true);
+    let one_by_one = time_frames(&mut ctx, &mut state)?;
+    state.draw_one_quad_per_piece(false);
+    let batched = time_frames(&mut ctx, &mut state)?;
+
+    println!("{} frames each way", FRAMES);
+    println!("one quad per piece: {:?} per frame", one_by_one / FRAMES);
+    println!("instance array:     {:?} per frame", batched / FRAMES);
+    println!("speedup: {:.1}x", one_by_one.as_secs_f64() / batched.as_secs_f64());
+    Ok(())
+}
+
+/// Has the game draw `FRAMES` frames and tells how long they took, leaving out
+/// the frames drawn to warm up. The game isn't updated in between, so every
+/// frame shows the same board.
+fn time_frames(ctx: &mut Context, state: &mut GameState) -> GameResult<Duration> {
+    let mut start = Instant::now();
+    for frame in 0..WARM_UP_FRAMES + FRAMES {
+        if frame == WARM_UP_FRAMES {
+            start = Instant::now();
+        }
+        ctx.gfx.begin_frame()?;
+        state.draw(ctx)?;
+        ctx.gfx.end_frame()?;
+    }
+    Ok(start.elapsed())
+}
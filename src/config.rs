@@ -0,0 +1,335 @@
+// The `Config` holds the settings that can be picked when the game is launched
+// instead of being baked in as constants. Everything that needs to know how big
+// the board is asks the `Config` rather than looking at `GRID_SIZE` directly.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DESIRED_FPS, GRID_CELL_SIZE, GRID_SIZE, MAX_BALL_SPEED};
+
+// A board must be wide enough for two padles with a court between them and tall
+// enough for a padle to stand upright in the middle of it.
+const MIN_GRID_SIZE: (i16, i16) = (3, 8);
+
+// More balls than this at once is more chaos than anybody can follow.
+const MAX_BALLS: u32 = 3;
+
+// A padle faster than this would cross the whole board in a couple of updates.
+const MAX_PADLE_SPEED: f32 = 4.0;
+
+// A ball bigger than this many cells across hardly leaves room to miss it.
+const MAX_BALL_SIZE: i16 = 3;
+
+// There is only room for so many padles on one edge of the board.
+const MAX_TEAM_SIZE: u32 = 2;
+
+/// How hard the game is. Easier games hand out longer padles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+/// Every difficulty, from the easiest to the hardest, in the order the menu
+/// goes through them.
+pub const DIFFICULTIES: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+impl Difficulty {
+    /// How many segments a padle has at this difficulty.
+    pub fn padle_length(self) -> i16 {
+        match self {
+            Difficulty::Easy => 7,
+            Difficulty::Normal => 5,
+            Difficulty::Hard => 3,
+        }
+    }
+
+    /// How many updates at `DESIRED_FPS` the computer player waits between looks
+    /// at the ball. In between it keeps going for wherever it decided on last.
+    pub fn ai_reaction_ticks(self) -> u32 {
+        match self {
+            Difficulty::Easy => 6,
+            Difficulty::Normal => 3,
+            Difficulty::Hard => 1,
+        }
+    }
+
+    /// How many rows the computer player may aim off by, either way, whenever
+    /// it picks where to go next.
+    pub fn ai_aim_error(self) -> i16 {
+        match self {
+            Difficulty::Easy => 3,
+            Difficulty::Normal => 1,
+            Difficulty::Hard => 0,
+        }
+    }
+
+    /// Whether the computer player works out where the ball will reach its padle,
+    /// rather than just following the row the ball is on right now.
+    pub fn ai_predicts(self) -> bool {
+        self == Difficulty::Hard
+    }
+
+    /// The name the menu shows for this difficulty.
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("Unknown difficulty {}, expected easy, normal or hard", s)),
+        }
+    }
+}
+
+/// Who the ball is served to after a point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServeRule {
+    /// Always to the player who just lost the point, so they get the first touch
+    #[default]
+    Loser,
+    /// To the same player for two points in a row, then to the other one for the
+    /// next two, like in table tennis, no matter who won them
+    Alternate,
+}
+
+impl std::str::FromStr for ServeRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loser" => Ok(ServeRule::Loser),
+            "alternate" => Ok(ServeRule::Alternate),
+            _ => Err(format!("Unknown serve rule {}, expected loser or alternate", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// How many cells the board has horizontally and vertically
+    pub grid_size: (i16, i16),
+    /// The size of a single cell in pixels
+    pub cell_size: (i16, i16),
+    /// How hard the game is
+    pub difficulty: Difficulty,
+    /// How many times per second the game updates
+    pub tick_rate: u32,
+    /// How many balls are in play at once
+    pub ball_count: u32,
+    /// How many cells wide and high every ball is
+    pub ball_size: i16,
+    /// How many pairs of obstacles are placed on the court
+    pub obstacles: u32,
+    /// How many cells a padle moves per update at `DESIRED_FPS`
+    pub padle_speed: f32,
+    /// Who the ball is served to after a point
+    pub serve_rule: ServeRule,
+    /// How much faster or slower than usual the balls fly
+    pub ball_speed: f32,
+    /// How many cells per update at `DESIRED_FPS` the padles can speed a ball up
+    /// to, before `ball_speed` is applied
+    pub max_ball_speed: f32,
+    /// How loud the sound effects are, from 0 for silent to 1 for full volume
+    pub volume: f32,
+    /// Whether the ball and padles are drawn with outlines, so they are easy to
+    /// tell apart without relying on color
+    pub outlines: bool,
+    /// Whether the balls move by the time each frame took instead of in fixed
+    /// updates. It looks smoother, but a replay of it may not play back exactly.
+    pub continuous: bool,
+    /// Whether the ball goes out through the top and comes back in at the bottom,
+    /// and the other way round, instead of bouncing off them. The padles still
+    /// stop at the edges.
+    pub torus: bool,
+    /// Whether the game slows down while a point could decide the match
+    pub slow_motion: bool,
+    /// Whether the balls wait for the serving player to send them off, up or
+    /// down, instead of going by themselves after a countdown
+    pub manual_serve: bool,
+    /// How many seconds a timed match lasts, or `None` for a match that goes on
+    /// until somebody has won enough rounds
+    pub time_limit: Option<u32>,
+    /// How many updates at `DESIRED_FPS` ahead the ghost ball shows where each
+    /// ball is going, or `None` for no ghost
+    pub ghost: Option<u32>,
+    /// How many padles each player's team has, stacked on the same edge
+    pub team_size: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            grid_size: GRID_SIZE,
+            cell_size: GRID_CELL_SIZE,
+            difficulty: Difficulty::default(),
+            tick_rate: DESIRED_FPS,
+            ball_count: 1,
+            ball_size: 1,
+            obstacles: 0,
+            padle_speed: 1.0,
+            serve_rule: ServeRule::default(),
+            ball_speed: 1.0,
+            max_ball_speed: MAX_BALL_SPEED,
+            volume: 1.0,
+            outlines: false,
+            continuous: false,
+            torus: false,
+            slow_motion: false,
+            manual_serve: false,
+            time_limit: None,
+            ghost: None,
+            team_size: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Builds a `Config` from command-line arguments. We understand
+    /// `--grid-width N`, `--grid-height N`, `--cell-size N`,
+    /// `--difficulty easy|normal|hard`, `--tick-rate N`, `--balls N`, `--ball-size N`,
+    /// `--obstacles N`, `--paddle-speed X`, `--max-ball-speed X`,
+    /// `--serve loser|alternate`, `--manual-serve`, `--continuous`, `--torus`,
+    /// `--slow-motion`, `--time-limit SECONDS`, `--ghost TICKS` and `--team-size N`, and anything
+    /// that isn't given keeps its default value.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--grid-width" => config.grid_size.0 = parse_size(&arg, &value()?)?,
+                "--grid-height" => config.grid_size.1 = parse_size(&arg, &value()?)?,
+                "--cell-size" => {
+                    let size = parse_size(&arg, &value()?)?;
+                    config.cell_size = (size, size);
+                }
+                "--difficulty" => config.difficulty = value()?.parse()?,
+                "--tick-rate" => config.tick_rate = parse_size(&arg, &value()?)? as u32,
+                "--balls" => config.ball_count = parse_size(&arg, &value()?)? as u32,
+                "--ball-size" => config.ball_size = parse_size(&arg, &value()?)?,
+                "--obstacles" => config.obstacles = parse_size(&arg, &value()?)? as u32,
+                "--paddle-speed" => config.padle_speed = parse_speed(&arg, &value()?)?,
+                "--max-ball-speed" => config.max_ball_speed = parse_speed(&arg, &value()?)?,
+                "--serve" => config.serve_rule = value()?.parse()?,
+                "--continuous" => config.continuous = true,
+                "--torus" => config.torus = true,
+                "--slow-motion" => config.slow_motion = true,
+                "--manual-serve" => config.manual_serve = true,
+                "--time-limit" => {
+                    config.time_limit = Some(parse_size(&arg, &value()?)? as u32);
+                }
+                "--ghost" => config.ghost = Some(parse_size(&arg, &value()?)? as u32),
+                "--team-size" => config.team_size = parse_size(&arg, &value()?)? as u32,
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks that the settings make a game we can play. The command line
+    /// already turns away sizes and speeds that aren't positive, but a `Config`
+    /// read from a save, a replay or another player hasn't been through that, so
+    /// we check those here as well.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.grid_size.0 < MIN_GRID_SIZE.0 || self.grid_size.1 < MIN_GRID_SIZE.1 {
+            return Err(format!(
+                "The board must be at least {} x {} cells",
+                MIN_GRID_SIZE.0, MIN_GRID_SIZE.1
+            ));
+        }
+
+        if self.cell_size.0 <= 0 || self.cell_size.1 <= 0 {
+            return Err("A cell must be at least one pixel across".to_string());
+        }
+
+        if self.tick_rate == 0 {
+            return Err("The game must update at least once per second".to_string());
+        }
+
+        if self.ball_count == 0 || self.ball_count > MAX_BALLS {
+            return Err(format!("Between 1 and {} balls can be in play", MAX_BALLS));
+        }
+
+        if self.ball_size <= 0 || self.ball_size > MAX_BALL_SIZE {
+            return Err(format!("A ball is 1 to {} cells across", MAX_BALL_SIZE));
+        }
+
+        if self.team_size == 0 || self.team_size > MAX_TEAM_SIZE {
+            return Err(format!("A team has 1 to {} padles", MAX_TEAM_SIZE));
+        }
+
+        if !(self.padle_speed > 0.0 && self.padle_speed <= MAX_PADLE_SPEED) {
+            return Err(format!("A padle moves at most {} cells per update", MAX_PADLE_SPEED));
+        }
+
+        if !(self.ball_speed > 0.0 && self.max_ball_speed > 0.0) {
+            return Err("The balls must move forward".to_string());
+        }
+
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err("The volume goes from 0 to 1".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// How large the window needs to be to show the whole board, which we get by
+    /// multiplying the grid size by the cell size.
+    pub fn screen_size(&self) -> (f32, f32) {
+        (
+            self.grid_size.0 as f32 * self.cell_size.0 as f32,
+            self.grid_size.1 as f32 * self.cell_size.1 as f32,
+        )
+    }
+
+    /// How many updates at `DESIRED_FPS` one of our updates is worth. Speeds are
+    /// measured per update at `DESIRED_FPS`, so they get multiplied by this to
+    /// stay the same in cells per second.
+    pub fn tick_scale(&self) -> f32 {
+        DESIRED_FPS as f32 / self.tick_rate as f32
+    }
+
+    /// Turns a number of updates at `DESIRED_FPS` into the number of our updates
+    /// that take just as long, rounding up.
+    pub fn ticks(&self, ticks_at_desired_fps: u32) -> u32 {
+        (ticks_at_desired_fps * self.tick_rate).div_ceil(DESIRED_FPS)
+    }
+}
+
+/// Parses the value of a size argument, which must be a positive whole number.
+fn parse_size(arg: &str, value: &str) -> Result<i16, String> {
+    match value.parse() {
+        Ok(size) if size > 0 => Ok(size),
+        Ok(_) => Err(format!("{} must be positive, got {}", arg, value)),
+        Err(_) => Err(format!("{} expects a whole number, got {}", arg, value)),
+    }
+}
+
+/// Parses the value of a speed argument, which must be a positive number but
+/// doesn't have to be a whole one.
+fn parse_speed(arg: &str, value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(speed) if speed > 0.0 => Ok(speed),
+        Ok(_) => Err(format!("{} must be positive, got {}", arg, value)),
+        Err(_) => Err(format!("{} expects a number, got {}", arg, value)),
+    }
+}
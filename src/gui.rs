@@ -0,0 +1,1170 @@
+// Everything that needs a window lives here: drawing the board and the screens
+// around it, loading the sounds and the background picture, and the
+// `EventHandler` that ggez drives the game through, along with turning ggez's
+// keys, buttons and rectangles into the game's own. It is only built with the
+// `gui` feature, which is on by default and is the only thing that brings in
+// ggez, so a program that embeds the game without a window doesn't have to
+// bring any of it along.
+
+use std::time::Instant;
+
+use ggez::{
+    event::{self, Button, GamepadId},
+    graphics,
+    input::keyboard::KeyInput,
+    Context, GameResult,
+};
+
+use crate::config::Config;
+use crate::highscores::{self, HighScores};
+use crate::keybindings::{self, Key};
+use crate::net::{Connection, Message, Peer};
+use crate::replay::{self, Replay};
+use crate::sounds::Sounds;
+use crate::theme::{Theme, THEMES};
+use crate::{
+    AppState, Ball, Direction, GameState, GridPosition, HeatMap, Padle, Particle, PlayerKind,
+    PowerUp, PowerUpKind, Setting, FLASH_TICKS, HIGHLIGHT_TICKS, MENU_OPTIONS, PARTICLE_LIFE,
+    SETTINGS, TRAIL_LENGTH,
+};
+
+// How many pixels wide the outlines around the ball and padles are.
+const OUTLINE_WIDTH: f32 = 2.0;
+
+// The flash for a point starts out at this opacity.
+const FLASH_ALPHA: f32 = 0.35;
+
+// A padle that hits the ball starts out this far of the way from its colors to
+// white.
+const HIGHLIGHT_STRENGTH: f32 = 0.5;
+
+// How many cells long the arrow showing where a serve will go is.
+const SERVE_ARROW_CELLS: f32 = 2.5;
+
+// How see-through the ghost ball is, which shows where a ball is going to be.
+const GHOST_ALPHA: f32 = 0.3;
+
+// The width in pixels of the mark on the far wall that shows where a padle is.
+const POSITION_MARK_WIDTH: f32 = 4.0;
+
+// How big the sparks are, in pixels.
+const PARTICLE_SIZE: f32 = 4.0;
+
+// The keys that switch something on or off. Holding one down switches it just
+// once, instead of back and forth as fast as the keyboard repeats the key.
+const TOGGLE_KEYS: [Key; 11] = [
+    Key::P,
+    Key::Escape,
+    Key::T,
+    Key::H,
+    Key::N,
+    Key::C,
+    Key::I,
+    Key::F3,
+    Key::F5,
+    Key::F9,
+    Key::F12,
+];
+
+/// Everything the game loads or builds for its window, which a game without
+/// one goes without.
+#[derive(Default)]
+pub(crate) struct Resources {
+    /// The sound effects
+    sounds: Sounds,
+    /// The picture drawn behind the board, if the players provided one
+    background: Option<graphics::Image>,
+    /// The batch the padles and balls are drawn in, made on the first frame and
+    /// refilled every frame after that
+    instances: Option<graphics::InstanceArray>,
+    /// Whether the padles and balls are drawn a `Quad` at a time instead
+    one_quad_per_piece: bool,
+}
+
+/// Somewhere the padles, balls and sparks can be drawn to, one rectangle at a
+/// time. The game puts them all in an `InstanceArray` to draw them at once.
+trait Pieces {
+    fn push(&mut self, param: graphics::DrawParam);
+}
+
+impl Pieces for graphics::InstanceArray {
+    fn push(&mut self, param: graphics::DrawParam) {
+        graphics::InstanceArray::push(self, param);
+    }
+}
+
+/// Draws every piece straight onto the canvas as a `Quad` of its own, the way
+/// the game did before it batched them.
+struct OneByOne<'a>(&'a mut graphics::Canvas);
+
+impl Pieces for OneByOne<'_> {
+    fn push(&mut self, param: graphics::DrawParam) {
+        self.0.draw(&graphics::Quad, param);
+    }
+}
+
+impl Config {
+    /// The `graphics::Rect` on screen that fills the cell at `pos`.
+    pub fn cell_rect(&self, pos: GridPosition) -> graphics::Rect {
+        graphics::Rect::new_i32(
+            pos.x as i32 * self.cell_size.0 as i32,
+            pos.y as i32 * self.cell_size.1 as i32,
+            self.cell_size.0 as i32,
+            self.cell_size.1 as i32,
+        )
+    }
+}
+
+impl Ball {
+    /// The `graphics::Rect` the ball fills on screen when its top left cell is at
+    /// `pos`.
+    fn rect_at(&self, pos: GridPosition, config: &Config) -> graphics::Rect {
+        let mut rect = config.cell_rect(pos);
+        rect.w *= self.size as f32;
+        rect.h *= self.size as f32;
+        rect
+    }
+
+    /// The cell the ball is drawn in. In continuous mode the ball is somewhere
+    /// between its cell and the next one, and is drawn in whichever it is closer
+    /// to.
+    fn draw_pos(&self, config: &Config) -> GridPosition {
+        if !config.continuous || self.progress < 0.5 {
+            return self.pos;
+        }
+        GridPosition::new(self.pos.x + self.velocity.dx, self.pos.y + self.velocity.dy)
+    }
+
+    /// Draws an arrow from the middle of the ball pointing the way it is about to
+    /// be served. Unlike the rest of the board it isn't made of cells, so it is a
+    /// `Mesh` of lines, which needs the `Context` to be built.
+    fn draw_serve_arrow(
+        &self,
+        ctx: &Context,
+        canvas: &mut graphics::Canvas,
+        config: &Config,
+        theme: &Theme,
+    ) -> GameResult {
+        // The velocity is in cells, so we turn it into pixels before finding the
+        // direction, in case the cells aren't square.
+        let cell = config.cell_rect(self.pos);
+        let rect = self.rect_at(self.pos, config);
+        let center = [rect.x + rect.w / 2.0, rect.y + rect.h / 2.0];
+        let dx = self.velocity.dx as f32 * cell.w;
+        let dy = self.velocity.dy as f32 * cell.h;
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return Ok(());
+        }
+        let (dx, dy) = (dx / length, dy / length);
+
+        // The shaft reaches a few cells ahead, and the two sides of the head sweep
+        // back from its tip.
+        let reach = SERVE_ARROW_CELLS * cell.w.max(cell.h);
+        let tip = [center[0] + dx * reach, center[1] + dy * reach];
+        let head = reach / 3.0;
+        let side = |turn: f32| {
+            [
+                tip[0] - (dx + dy * turn) * head,
+                tip[1] - (dy - dx * turn) * head,
+            ]
+        };
+        let mesh = graphics::Mesh::from_data(
+            ctx,
+            graphics::MeshBuilder::new()
+                .line(&[center, tip], OUTLINE_WIDTH, theme.ball.into())?
+                .line(&[side(0.5), tip, side(-0.5)], OUTLINE_WIDTH, theme.ball.into())?
+                .build(),
+        );
+        canvas.draw(&mesh, graphics::DrawParam::new());
+        Ok(())
+    }
+
+    /// Draws a faint copy of the ball where it will be `ticks` updates from now.
+    fn draw_ghost(
+        &self,
+        pieces: &mut impl Pieces,
+        ticks: u32,
+        config: &Config,
+        theme: &Theme,
+    ) {
+        let mut color = theme.ball;
+        color[3] *= GHOST_ALPHA;
+        pieces.push(
+            graphics::DrawParam::new()
+                .dest_rect(self.rect_at(self.predicted_pos(ticks, config), config))
+                .color(color),
+        );
+    }
+
+    /// Here is the first time we see what drawing looks like with ggez.
+    /// Rather than drawing every rectangle on the canvas by itself, we add them
+    /// to an `InstanceArray`, which the game then draws all at once. That keeps
+    /// the number of draw calls down however many balls and cells there are.
+    fn draw(&self, pieces: &mut impl Pieces, config: &Config, theme: &Theme) {
+        // First we set the color to draw with, which the theme decides.
+        let color = theme.ball;
+
+        // Behind the ball comes its trail, fading out the older a cell is, so the
+        // last cell is barely visible.
+        for (age, pos) in self.trail.iter().enumerate() {
+            let mut faded = color;
+            faded[3] *= 0.5 * (TRAIL_LENGTH - age) as f32 / (TRAIL_LENGTH + 1) as f32;
+            pieces.push(
+                graphics::DrawParam::new()
+                    .dest_rect(self.rect_at(*pos, config))
+                    .color(faded),
+            );
+        }
+
+        // Then we draw a rectangle with the Fill draw mode, and we let the config
+        // convert the ball's position into the `ggez::Rect` of its cell, stretched
+        // over however many cells the ball covers.
+        let rect = self.rect_at(self.draw_pos(config), config);
+        pieces.push(graphics::DrawParam::new().dest_rect(rect).color(color));
+
+        // With outlines on, the ball gets a frame and a hole punched in its middle,
+        // so its shape sets it apart even where its color doesn't
+        if config.outlines {
+            draw_outline(pieces, rect, theme.outline);
+            let hole = graphics::Rect::new(
+                rect.x + rect.w / 3.0,
+                rect.y + rect.h / 3.0,
+                rect.w / 3.0,
+                rect.h / 3.0,
+            );
+            pieces.push(graphics::DrawParam::new().dest_rect(hole).color(theme.background));
+        }
+    }
+}
+
+impl HeatMap {
+    /// We draw every visited cell in red, with the most visited cell fully
+    /// opaque and all the others scaled relative to it.
+    fn draw(&self, canvas: &mut graphics::Canvas, config: &Config) {
+        let max = self.cells.iter().copied().max().unwrap_or(0);
+        if max == 0 {
+            return;
+        }
+
+        for y in 0..self.grid_size.1 {
+            for x in 0..self.grid_size.0 {
+                let pos = GridPosition::new(x, y);
+                let count = self.cells[self.index(pos)];
+                if count == 0 {
+                    continue;
+                }
+                let heat = count as f32 / max as f32;
+                canvas.draw(
+                    &graphics::Quad,
+                    graphics::DrawParam::new()
+                        .dest_rect(config.cell_rect(pos))
+                        .color([1.0, 0.2, 0.0, 0.15 + 0.6 * heat]),
+                );
+            }
+        }
+    }
+}
+
+impl PowerUp {
+    /// Power-ups are drawn in green when they grow a padle and in purple when
+    /// they shrink it.
+    fn draw(&self, canvas: &mut graphics::Canvas, config: &Config) {
+        let color = match self.kind {
+            PowerUpKind::Grow => [0.0, 1.0, 0.3, 1.0],
+            PowerUpKind::Shrink => [0.7, 0.0, 1.0, 1.0],
+        };
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(config.cell_rect(self.pos))
+                .color(color),
+        );
+    }
+}
+
+impl Particle {
+    /// Adds the spark to `pieces` in `color`, fading out as it burns out.
+    fn draw(&self, pieces: &mut impl Pieces, color: [f32; 4]) {
+        let mut color = color;
+        color[3] *= (self.life / PARTICLE_LIFE).clamp(0.0, 1.0);
+        let half = PARTICLE_SIZE / 2.0;
+        pieces.push(
+            graphics::DrawParam::new()
+                .dest_rect(graphics::Rect::new(
+                    self.pos.0 - half,
+                    self.pos.1 - half,
+                    PARTICLE_SIZE,
+                    PARTICLE_SIZE,
+                ))
+                .color(color),
+        );
+    }
+}
+
+impl Setting {
+    /// What the setting is called on screen.
+    fn label(self) -> &'static str {
+        match self {
+            Setting::Volume => "Volume",
+            Setting::BallSpeed => "Ball speed",
+            Setting::Colors => "Colors",
+            Setting::Outlines => "Outlines",
+            Setting::Torus => "Wrap top and bottom",
+        }
+    }
+
+    /// The setting's current value, written the way players expect to read it.
+    fn describe(self, config: &Config, theme: &Theme) -> String {
+        match self {
+            Setting::Volume => format!("{:.0}%", config.volume * 100.0),
+            Setting::BallSpeed => format!("x{:.1}", config.ball_speed),
+            Setting::Colors => theme.name.to_string(),
+            Setting::Outlines if config.outlines => "On".to_string(),
+            Setting::Outlines => "Off".to_string(),
+            Setting::Torus if config.torus => "On".to_string(),
+            Setting::Torus => "Off".to_string(),
+        }
+    }
+}
+
+impl Padle {
+    /// Here we have the Padle draw itself. 
+    ///
+    /// Just like the ball, it adds its rectangles to the `InstanceArray` the game
+    /// draws in one go, instead of drawing each segment separately.
+    fn draw(&self, pieces: &mut impl Pieces, config: &Config, theme: &Theme) {
+        // Right after hitting the ball the padle is lit up, fading back to its
+        // own colors
+        let glow = HIGHLIGHT_STRENGTH * self.highlight_timer as f32
+            / config.ticks(HIGHLIGHT_TICKS) as f32;
+
+        // We first iterate through the body segments and draw them.
+        for seg in &self.body {
+            // Each segment brings its own color, and we draw the Rect that we
+            // convert that Segment's position into
+            let mut color = seg.color;
+            for channel in &mut color[..3] {
+                *channel += (1.0 - *channel) * glow;
+            }
+            pieces.push(
+                graphics::DrawParam::new()
+                    .dest_rect(config.cell_rect(seg.pos))
+                    .color(color),
+            );
+        }
+
+        // With outlines on, the whole padle gets a frame so it stands out from the
+        // court whatever its colors
+        if let (true, Some(front), Some(back)) =
+            (config.outlines, self.body.front(), self.body.back())
+        {
+            let rect = config.cell_rect(front.pos).combine_with(config.cell_rect(back.pos));
+            draw_outline(pieces, rect, theme.outline);
+        }
+    }
+
+    /// Adds a thin bar in the padle's bottom color to the wall across the board
+    /// from it, level with its center segment. A player watching the ball near
+    /// the other side can see where their padle is out of the corner of their eye.
+    fn draw_position_mark(&self, pieces: &mut impl Pieces, config: &Config) {
+        let center = config.cell_rect(self.center());
+        let screen_width = config.screen_size().0;
+        let x = if center.x < screen_width / 2.0 {
+            screen_width - POSITION_MARK_WIDTH
+        } else {
+            0.0
+        };
+        pieces.push(
+            graphics::DrawParam::new()
+                .dest_rect(graphics::Rect::new(x, center.y, POSITION_MARK_WIDTH, center.h))
+                .color(self.colors.0),
+        );
+    }
+
+    /// When debugging we want to see exactly where the paddle is, so this draws the
+    /// grid coordinates of the front and back segments next to them. The text goes
+    /// on whichever side of the paddle faces the court.
+    fn draw_coordinates(&self, canvas: &mut graphics::Canvas, config: &Config) {
+        for seg in [self.body.front(), self.body.back()].into_iter().flatten() {
+            let mut text = graphics::Text::new(format!("({}, {})", seg.pos.x, seg.pos.y));
+            text.set_scale(14.0);
+
+            let rect = config.cell_rect(seg.pos);
+            let dest = if seg.pos.x < config.grid_size.0 / 2 {
+                [rect.right() + 4.0, rect.y]
+            } else {
+                text.set_layout(graphics::TextLayout {
+                    h_align: graphics::TextAlign::End,
+                    v_align: graphics::TextAlign::Begin,
+                });
+                [rect.left() - 4.0, rect.y]
+            };
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new().dest(dest).color([1.0, 1.0, 0.0, 1.0]),
+            );
+        }
+    }
+}
+
+impl GameState {
+    /// Our new function will set up the initial state of our game, seeding our RNG
+    /// with the system RNG so that every game plays out differently.
+    pub fn new(ctx: &Context, config: Config) -> GameResult<Self> {
+        let mut seed: [u8; 8] = [0; 8];
+        getrandom::getrandom(&mut seed[..]).map_err(|e| {
+            ggez::GameError::CustomError(format!("Could not create RNG seed: {}", e))
+        })?;
+        let mut state = GameState::with_seed(u64::from_ne_bytes(seed), config);
+
+        // A real game also remembers the high scores from earlier sessions. If they
+        // can't be read we carry on from zeros rather than refusing to play.
+        state.high_scores_path = highscores::default_path();
+        if let Some(path) = &state.high_scores_path {
+            state.high_scores = highscores::load_high_scores(path).unwrap_or_else(|e| {
+                eprintln!("Could not load high scores from {}: {}", path.display(), e);
+                HighScores::default()
+            });
+        }
+
+        // Every match is recorded, and the last one finished is kept
+        state.replay_path = replay::default_path();
+
+        state.load_resources(ctx)?;
+        Ok(state)
+    }
+
+    /// Sets up a game with a window that plays back `replay`. Nothing that happens
+    /// in it counts towards the high scores.
+    pub fn replaying(ctx: &Context, replay: Replay) -> GameResult<Self> {
+        let mut state = GameState::from_replay(replay);
+        state.load_resources(ctx)?;
+        Ok(state)
+    }
+
+    /// Sets up a game with a window that the other end of `connection` joins as
+    /// player 2. We send them the seed and settings so they can build the same
+    /// board, and the match starts right away. Their key presses never reach our
+    /// recording, so these matches aren't saved as replays.
+    pub fn hosting(ctx: &Context, config: Config, mut connection: Connection) -> GameResult<Self> {
+        let mut state = GameState::new(ctx, config)?;
+        state.replay_path = None;
+        let hello = Message::Hello {
+            seed: state.recording.seed,
+            config,
+        };
+        connection.send(&hello).map_err(|e| {
+            ggez::GameError::CustomError(format!("Could not greet the other player: {}", e))
+        })?;
+        state.peer = Some(Peer::Host(connection));
+        state.start_match();
+        Ok(state)
+    }
+
+    /// Sets up a game with a window that joins the game hosted at the other end of
+    /// `connection` as player 2, from the `seed` and `config` the host sent.
+    pub fn joining(
+        ctx: &Context,
+        seed: u64,
+        config: Config,
+        connection: Connection,
+    ) -> GameResult<Self> {
+        // The host decides where the balls are, so we never move them ourselves,
+        // not even between updates.
+        let config = Config {
+            continuous: false,
+            ..config
+        };
+        let mut state = GameState::with_seed(seed, config);
+        state.load_resources(ctx)?;
+        state.peer = Some(Peer::Join(connection));
+        state.app_state = AppState::Playing;
+        Ok(state)
+    }
+
+    /// Loads the sound effects, the background image and the players' key
+    /// bindings.
+    fn load_resources(&mut self, ctx: &Context) -> GameResult {
+        self.resources.sounds = Sounds::load(ctx);
+
+        // Anybody can put a `background.png` next to the sounds to play on it.
+        // Without one the board is simply cleared to the theme's color.
+        self.resources.background = graphics::Image::from_path(ctx, "/background.png").ok();
+
+        // The players may have remapped their keys. A file we can't make sense of
+        // stops the game, because playing with the wrong keys would be worse.
+        if let Some(path) = keybindings::default_path() {
+            self.key_bindings = keybindings::load_key_bindings(&path).map_err(|e| {
+                ggez::GameError::CustomError(format!(
+                    "Could not load key bindings from {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            if self.key_bindings.preset_index().is_none() {
+                self.own_key_bindings = Some(self.key_bindings.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Has the padles, balls and sparks drawn a `Quad` at a time rather than in
+    /// one batch. It is slower, which is what the drawing benchmark measures.
+    pub fn draw_one_quad_per_piece(&mut self, one_by_one: bool) {
+        self.resources.one_quad_per_piece = one_by_one;
+    }
+
+    /// The theme the game is drawn in.
+    fn theme(&self) -> &Theme {
+        &THEMES[self.theme_index]
+    }
+
+    /// Moves the padle a gamepad controls in `dir`, or stops it, when the button
+    /// goes down or up. The padle comes from the gamepad's place among the
+    /// connected gamepads: the first one plays player 1, the second player 2
+    /// and so on. A padle played by the computer doesn't take gamepad input.
+    fn steer_with_gamepad(&mut self, ctx: &Context, id: GamepadId, dir: Direction, pressed: bool) {
+        let Some(index) = ctx.gamepad.gamepads().position(|(pad_id, _)| pad_id == id) else {
+            return;
+        };
+        if matches!(self.peer, Some(Peer::Join(_))) {
+            if index == 0 {
+                self.steer_joined(dir, pressed);
+            }
+            return;
+        }
+        let player = u8::try_from(index + 1).ok();
+        if let Some(padle) = player.and_then(|player| self.player_padle(player)) {
+            if pressed {
+                padle.press(dir);
+            } else {
+                padle.release(dir);
+            }
+        }
+    }
+
+    /// Draws the main menu, highlighting the option that is currently selected.
+    fn draw_menu(&self, canvas: &mut graphics::Canvas) {
+        let screen_size = self.config.screen_size();
+        draw_centered_text(
+            canvas,
+            "Moving Paddles",
+            72.0,
+            [screen_size.0 / 2.0, screen_size.1 / 4.0],
+            [1.0, 1.0, 1.0, 1.0],
+        );
+
+        for (index, (label, _)) in MENU_OPTIONS.iter().enumerate() {
+            let color = if index == self.menu_selection {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [0.5, 0.5, 0.5, 1.0]
+            };
+            draw_centered_text(
+                canvas,
+                label,
+                40.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + index as f32 * 56.0],
+                color,
+            );
+        }
+
+        // Underneath the players we show the difficulty, which Left and Right change
+        draw_centered_text(
+            canvas,
+            &format!("< Difficulty: {} >", self.config.difficulty.label()),
+            28.0,
+            [
+                screen_size.0 / 2.0,
+                screen_size.1 / 2.0 + MENU_OPTIONS.len() as f32 * 56.0 + 16.0,
+            ],
+            [0.8, 0.8, 0.8, 1.0],
+        );
+
+        // And below that the controls, which Tab changes
+        let controls = match self.key_bindings.preset_index() {
+            Some(index) => keybindings::PRESETS[index].0,
+            None => "your own keys",
+        };
+        draw_centered_text(
+            canvas,
+            &format!("Controls: {} (Tab)", controls),
+            22.0,
+            [
+                screen_size.0 / 2.0,
+                screen_size.1 / 2.0 + MENU_OPTIONS.len() as f32 * 56.0 + 56.0,
+            ],
+            [0.8, 0.8, 0.8, 1.0],
+        );
+    }
+
+    /// Draws the padles, the balls with their ghosts and the sparks into `pieces`.
+    fn draw_pieces(&self, pieces: &mut impl Pieces) {
+        let teams = if self.player2 == PlayerKind::Wall { 1 } else { 2 };
+        for padle in self.padles[..teams].iter().flatten() {
+            padle.draw(pieces, &self.config, self.theme());
+            padle.draw_position_mark(pieces, &self.config);
+        }
+        // For practice, a ghost shows where each ball is headed once it is on
+        // its way
+        let served = self.serve_timer == 0 && !self.waiting_for_serve;
+        if let Some(ticks) = self.config.ghost {
+            if served && self.app_state == AppState::Playing {
+                for ball in &self.balls {
+                    ball.draw_ghost(pieces, ticks, &self.config, self.theme());
+                }
+            }
+        }
+        for ball in &self.balls {
+            ball.draw(pieces, &self.config, self.theme());
+        }
+        for particle in &self.particles {
+            particle.draw(pieces, self.theme().ball);
+        }
+    }
+
+    /// Draws the board and everything on it, along with whatever message the
+    /// current moment of the game calls for.
+    fn draw_game(
+        &self,
+        ctx: &Context,
+        canvas: &mut graphics::Canvas,
+        instances: &mut graphics::InstanceArray,
+    ) -> GameResult {
+        let screen_size = self.config.screen_size();
+
+        // If asked to, we draw the heat map first so everything else ends up on top of it
+        if self.show_heat_map {
+            self.heat_map.draw(canvas, &self.config);
+        }
+
+        // The scores and the net go underneath everything that moves
+        self.draw_score(canvas);
+        self.draw_net(canvas);
+
+        // Then the obstacles
+        for obstacle in &self.obstacles {
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(self.config.cell_rect(*obstacle))
+                    .color(self.theme().obstacle),
+            );
+        }
+
+        // Then the power-up waiting on the court, if there is one
+        if let Some(power_up) = &self.power_up {
+            power_up.draw(canvas, &self.config);
+        }
+
+        // Then we tell the padles and the balls to draw themselves. They all go
+        // into one batch that is drawn with a single call.
+        if self.resources.one_quad_per_piece {
+            self.draw_pieces(&mut OneByOne(canvas));
+        } else {
+            instances.clear();
+            self.draw_pieces(instances);
+            canvas.draw(instances, graphics::DrawParam::new());
+        }
+
+        // While the balls wait to be served, we show which way they will go
+        if self.serve_timer > 0 && self.app_state == AppState::Playing {
+            for ball in &self.balls {
+                ball.draw_serve_arrow(ctx, canvas, &self.config, self.theme())?;
+            }
+        }
+
+        // Right after a point the winner's half of the board lights up, unless
+        // that point ended the match
+        if self.flash_timer > 0 && self.app_state != AppState::GameOver {
+            let fade = self.flash_timer as f32 / self.config.ticks(FLASH_TICKS) as f32;
+            let [r, g, b, _] = self.theme().players[usize::from(self.flash_player - 1)];
+            let x = if self.flash_player == 1 { 0.0 } else { screen_size.0 / 2.0 };
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(graphics::Rect::new(x, 0.0, screen_size.0 / 2.0, screen_size.1))
+                    .color([r, g, b, FLASH_ALPHA * fade]),
+            );
+        }
+
+        // And when debugging, we label the paddle ends with their coordinates
+        if self.show_coordinates {
+            let teams = if self.player2 == PlayerKind::Wall { 1 } else { 2 };
+            for padle in self.padles[..teams].iter().flatten() {
+                padle.draw_coordinates(canvas, &self.config);
+            }
+        }
+
+        // While a serve is coming up we count down the seconds to it
+        if self.serve_timer > 0 && self.app_state == AppState::Playing {
+            let seconds = self.serve_timer.div_ceil(self.config.tick_rate);
+            draw_centered_text(
+                canvas,
+                &seconds.to_string(),
+                96.0,
+                [screen_size.0 / 2.0, screen_size.1 / 3.0],
+                [1.0, 1.0, 1.0, 0.8],
+            );
+        }
+
+        // And when the balls wait to be served by hand, we say whose turn it is
+        if self.waiting_for_serve && self.app_state == AppState::Playing {
+            draw_centered_text(
+                canvas,
+                &format!("Player {} serves: up or down", self.server),
+                40.0,
+                [screen_size.0 / 2.0, screen_size.1 / 3.0],
+                [1.0, 1.0, 1.0, 0.8],
+            );
+        }
+
+        // Once somebody has won the match we announce it in the middle of the
+        // screen, and underneath we show the records kept across sessions
+        if let Some(winner) = self.match_winner() {
+            draw_centered_text(
+                canvas,
+                &format!("Player {} wins the match!", winner),
+                64.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0],
+                [1.0, 1.0, 0.0, 1.0],
+            );
+            draw_centered_text(
+                canvas,
+                &format!(
+                    "Wins: {} - {}    Best game: {} points",
+                    self.high_scores.wins1, self.high_scores.wins2, self.high_scores.best_combined
+                ),
+                24.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + 60.0],
+                [1.0, 1.0, 1.0, 1.0],
+            );
+            draw_centered_text(
+                canvas,
+                &format!("Longest rally: {} hits", self.longest_rally),
+                24.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + 95.0],
+                [1.0, 1.0, 1.0, 1.0],
+            );
+            draw_centered_text(
+                canvas,
+                "Press R to play again",
+                24.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + 135.0],
+                [0.8, 0.8, 0.8, 1.0],
+            );
+        }
+
+        // While the settings are open we list them over the board, otherwise while
+        // paused we dim a band across the middle of the board and say so
+        if let Some(selection) = self.settings_selection {
+            self.draw_settings(canvas, selection);
+        } else if self.paused {
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(graphics::Rect::new(
+                        0.0,
+                        screen_size.1 / 2.0 - 50.0,
+                        screen_size.0,
+                        100.0,
+                    ))
+                    .color([0.0, 0.0, 0.0, 0.6]),
+            );
+            draw_centered_text(
+                canvas,
+                "PAUSED",
+                64.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 - 10.0],
+                [1.0, 1.0, 1.0, 1.0],
+            );
+            draw_centered_text(
+                canvas,
+                "P to resume, O for settings",
+                20.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + 34.0],
+                [0.8, 0.8, 0.8, 1.0],
+            );
+        }
+        Ok(())
+    }
+
+    /// Draws the settings overlay with every setting and its value, highlighting
+    /// the one at `selection`.
+    fn draw_settings(&self, canvas: &mut graphics::Canvas, selection: usize) {
+        let screen_size = self.config.screen_size();
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::new()
+                .dest_rect(graphics::Rect::new(0.0, 0.0, screen_size.0, screen_size.1))
+                .color([0.0, 0.0, 0.0, 0.75]),
+        );
+        draw_centered_text(
+            canvas,
+            "Settings",
+            56.0,
+            [screen_size.0 / 2.0, screen_size.1 / 4.0],
+            [1.0, 1.0, 1.0, 1.0],
+        );
+
+        for (index, setting) in SETTINGS.iter().enumerate() {
+            let color = if index == selection {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [0.5, 0.5, 0.5, 1.0]
+            };
+            draw_centered_text(
+                canvas,
+                &format!(
+                    "{}   < {} >",
+                    setting.label(),
+                    setting.describe(&self.config, self.theme())
+                ),
+                32.0,
+                [screen_size.0 / 2.0, screen_size.1 / 2.0 + index as f32 * 48.0],
+                color,
+            );
+        }
+
+        draw_centered_text(
+            canvas,
+            "Arrows change the settings, Escape goes back",
+            20.0,
+            [screen_size.0 / 2.0, screen_size.1 * 3.0 / 4.0],
+            [0.8, 0.8, 0.8, 1.0],
+        );
+    }
+
+    /// Draws the net as a dashed line down the center column, filling every other
+    /// cell with a thin stripe. It is only for looks, the ball flies right through.
+    fn draw_net(&self, canvas: &mut graphics::Canvas) {
+        let x = self.config.grid_size.0 / 2;
+        for y in (0..self.config.grid_size.1).step_by(2) {
+            let cell = self.config.cell_rect(GridPosition::new(x, y));
+            canvas.draw(
+                &graphics::Quad,
+                graphics::DrawParam::new()
+                    .dest_rect(graphics::Rect::new(
+                        cell.x + cell.w * 3.0 / 8.0,
+                        cell.y,
+                        cell.w / 4.0,
+                        cell.h,
+                    ))
+                    .color(self.theme().net),
+            );
+        }
+    }
+
+    /// Draws both scores at the top of the board, player 1 on the left half and
+    /// player 2 on the right half, with the rounds won so far in between.
+    fn draw_score(&self, canvas: &mut graphics::Canvas) {
+        let screen_size = self.config.screen_size();
+
+        // Practicing alone there is no score, only the rally going on
+        if self.player2 == PlayerKind::Wall {
+            draw_centered_text(
+                canvas,
+                &format!("Rally {}", self.current_rally),
+                48.0,
+                [screen_size.0 / 2.0, 32.0],
+                [1.0, 1.0, 1.0, 1.0],
+            );
+            return;
+        }
+
+        // A timed match shows the time left instead of the rounds, or that the next
+        // point wins it once time ran out on a tie
+        let heading = match self.time_limit {
+            Some(_) if self.time_is_up() => "Sudden death".to_string(),
+            Some(limit) => {
+                let left = (limit - self.elapsed).as_secs_f32().ceil() as u32;
+                format!("{}:{:02}", left / 60, left % 60)
+            }
+            None => format!("Rounds {} - {}", self.rounds_won1, self.rounds_won2),
+        };
+        draw_centered_text(
+            canvas,
+            &heading,
+            24.0,
+            [screen_size.0 / 2.0, 24.0],
+            [0.8, 0.8, 0.8, 1.0],
+        );
+        if self.current_rally > 0 {
+            draw_centered_text(
+                canvas,
+                &format!("Rally {}", self.current_rally),
+                20.0,
+                [screen_size.0 / 2.0, 52.0],
+                [0.8, 0.8, 0.8, 1.0],
+            );
+        }
+        let columns = [
+            (self.score1, screen_size.0 / 4.0, self.theme().players[0]),
+            (self.score2, screen_size.0 * 3.0 / 4.0, self.theme().players[1]),
+        ];
+        for (score, x, color) in columns {
+            let mut text = graphics::Text::new(score.to_string());
+            text.set_scale(48.0).set_layout(graphics::TextLayout {
+                h_align: graphics::TextAlign::Middle,
+                v_align: graphics::TextAlign::Begin,
+            });
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new().dest([x, 8.0]).color(color),
+            );
+        }
+    }
+}
+
+/// Gamepads move the padles the same way the keyboard does: the D-pad's
+/// `Button::DPadUp` moves up and `Button::DPadDown` moves down. No other
+/// button represents a direction.
+fn button_direction(btn: Button) -> Option<Direction> {
+    match btn {
+        Button::DPadUp => Some(Direction::Up),
+        Button::DPadDown => Some(Direction::Down),
+        _ => None,
+    }
+}
+
+/// Adds a frame `OUTLINE_WIDTH` pixels wide just inside `rect` to `pieces`.
+fn draw_outline(pieces: &mut impl Pieces, rect: graphics::Rect, color: [f32; 4]) {
+    let width = OUTLINE_WIDTH;
+    let sides = [
+        graphics::Rect::new(rect.x, rect.y, rect.w, width),
+        graphics::Rect::new(rect.x, rect.bottom() - width, rect.w, width),
+        graphics::Rect::new(rect.x, rect.y, width, rect.h),
+        graphics::Rect::new(rect.right() - width, rect.y, width, rect.h),
+    ];
+    for side in sides {
+        pieces.push(graphics::DrawParam::new().dest_rect(side).color(color));
+    }
+}
+
+/// Most of the text in the game is drawn centered on a point, so this helper takes
+/// care of the layout.
+fn draw_centered_text(
+    canvas: &mut graphics::Canvas,
+    text: &str,
+    scale: f32,
+    dest: [f32; 2],
+    color: [f32; 4],
+) {
+    let mut text = graphics::Text::new(text);
+    text.set_scale(scale).set_layout(graphics::TextLayout {
+        h_align: graphics::TextAlign::Middle,
+        v_align: graphics::TextAlign::Middle,
+    });
+    canvas.draw(&text, graphics::DrawParam::new().dest(dest).color(color));
+}
+
+/// Now we implement `EventHandler` for `GameState`. This provides an interface
+/// that ggez will call automatically when different events happen.
+impl event::EventHandler<ggez::GameError> for GameState {
+    /// Update will happen on every frame before it is drawn. This is where we update
+    /// our game state to react to whatever is happening in the game world.
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        let start = Instant::now();
+
+        // Rely on ggez's built-in timer for deciding when to update the game, and how many times.
+        // If the update is early, there will be no cycles, otherwises, the logic will run once for each
+        // frame fitting in the time since the last update. In slow motion there are fewer of
+        // them to the second.
+        while ctx.time.check_update_time(self.update_rate()) {
+            // Here we do the actual updating of our game world, and then play a
+            // sound for whatever happened.
+            if let Some(sound) = self.advance() {
+                self.resources.sounds.play(ctx, sound, self.config.volume);
+            }
+        }
+
+        // In continuous mode the balls also move by however long the frame took
+        if let Some(sound) = self.advance_frame(ctx.time.delta()) {
+            self.resources.sounds.play(ctx, sound, self.config.volume);
+        }
+
+        // Whatever went wrong along the way doesn't stop the game, so we just
+        // pass it on.
+        for notice in self.take_notices() {
+            eprintln!("{}", notice);
+        }
+
+        self.update_time = start.elapsed();
+        Ok(())
+    }
+
+    /// draw is where we should actually render the game's current state.
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let start = Instant::now();
+
+        // First we create a canvas that renders to the frame, and clear it to a (sort of) green color
+        let mut canvas =
+            graphics::Canvas::from_frame(ctx, graphics::Color::from(self.theme().background));
+        // Everything is drawn in the board's own pixels, and the canvas stretches
+        // them over the whole window however big it is. While the screen shakes
+        // we look at the board from a little to the side, but only while the game is
+        // running.
+        let screen_size = self.config.screen_size();
+        let (x, y) = if self.app_state == AppState::Playing && !self.paused {
+            self.shake_offset
+        } else {
+            (0.0, 0.0)
+        };
+        canvas.set_screen_coordinates(graphics::Rect::new(x, y, screen_size.0, screen_size.1));
+
+        // The background image, if there is one, is stretched over the whole board
+        // before anything else is drawn
+        if let Some(image) = &self.resources.background {
+            let scale = [
+                screen_size.0 / image.width() as f32,
+                screen_size.1 / image.height() as f32,
+            ];
+            canvas.draw(image, graphics::DrawParam::new().scale(scale));
+        }
+
+        // The menu has a screen of its own, and the demo plays dimmed behind it.
+        // Everything else is drawn on the board.
+        let mut instances = self
+            .resources
+            .instances
+            .take()
+            .unwrap_or_else(|| graphics::InstanceArray::new(ctx, None));
+        match self.app_state {
+            AppState::Menu => self.draw_menu(&mut canvas),
+            AppState::Demo => {
+                self.draw_game(ctx, &mut canvas, &mut instances)?;
+                canvas.draw(
+                    &graphics::Quad,
+                    graphics::DrawParam::new()
+                        .dest_rect(graphics::Rect::new(0.0, 0.0, screen_size.0, screen_size.1))
+                        .color([0.0, 0.0, 0.0, 0.7]),
+                );
+                self.draw_menu(&mut canvas);
+            }
+            _ => self.draw_game(ctx, &mut canvas, &mut instances)?,
+        }
+        self.resources.instances = Some(instances);
+
+        // When asked for, we show how fast the game runs on top of everything. The
+        // draw time shown is the last frame's, since this one isn't done yet.
+        if self.show_performance {
+            let mut text = graphics::Text::new(format!(
+                "FPS {:.1}  update {:.2} ms  draw {:.2} ms",
+                ctx.time.fps(),
+                self.update_time.as_secs_f64() * 1000.0,
+                self.draw_time.as_secs_f64() * 1000.0,
+            ));
+            text.set_scale(16.0);
+            canvas.draw(
+                &text,
+                graphics::DrawParam::new().dest([x + 4.0, y + 4.0]).color([1.0, 1.0, 0.0, 1.0]),
+            );
+        }
+
+        // Finally, we "flush" the draw commands.
+        // Since we rendered to the frame, we don't need to tell ggez to present anything else,
+        // as ggez will automatically present the frame image unless told otherwise.
+        canvas.finish(ctx)?;
+        self.draw_time = start.elapsed();
+
+        // We yield the current thread until the next update
+        ggez::timer::yield_now();
+        // And return success.
+        Ok(())
+    }
+
+    /// `resize_event` gets fired when the window changes size. Drawing adapts by
+    /// itself, but we need the new size to know where the mouse is on the board.
+    fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.window_size = (width, height);
+        Ok(())
+    }
+
+    /// `focus_event` gets fired when the window gains or loses focus. A match
+    /// going on behind another window would be unfair, so losing focus pauses it,
+    /// and it stays paused until a player resumes it. Replays play on regardless.
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) -> GameResult {
+        if !gained && self.app_state == AppState::Playing && self.playback.is_none() {
+            self.paused = true;
+        }
+        Ok(())
+    }
+
+    /// `gamepad_button_down_event` gets fired when a button on a gamepad gets pressed.
+    /// The first connected gamepad plays player 1 and the second plays player 2,
+    /// unless a replay is playing.
+    fn gamepad_button_down_event(
+        &mut self,
+        ctx: &mut Context,
+        btn: Button,
+        id: GamepadId,
+    ) -> GameResult {
+        if self.app_state != AppState::Playing || self.paused || self.playback.is_some() {
+            return Ok(());
+        }
+
+        if let Some(dir) = button_direction(btn) {
+            self.steer_with_gamepad(ctx, id, dir, true);
+        }
+        Ok(())
+    }
+
+    /// `gamepad_button_up_event` gets fired when a gamepad button is let go, which
+    /// stops the padle moving that way.
+    fn gamepad_button_up_event(
+        &mut self,
+        ctx: &mut Context,
+        btn: Button,
+        id: GamepadId,
+    ) -> GameResult {
+        if let Some(dir) = button_direction(btn) {
+            self.steer_with_gamepad(ctx, id, dir, false);
+        }
+        Ok(())
+    }
+
+    /// `key_down_event` gets fired when a key gets pressed, and again every so
+    /// often while it is held, with `repeat` set. Those repeats are left out for
+    /// the keys that switch something on or off. While a replay is playing, the
+    /// keys come from the recording instead. Keys the game doesn't know about are
+    /// left alone.
+    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, repeat: bool) -> GameResult {
+        let key = input.keycode.and_then(Key::from_key_code);
+        if let (Some(key), None) = (key, &self.playback) {
+            if repeat && TOGGLE_KEYS.contains(&key) {
+                return Ok(());
+            }
+            self.key_down(key);
+        }
+
+        Ok(())
+    }
+
+    /// `mouse_motion_event` gets fired whenever the mouse moves over the window, and
+    /// drags the padle on that side along. ggez turns a finger dragged across a
+    /// touchscreen into mouse motion too, so that works the same way.
+    fn mouse_motion_event(
+        &mut self,
+        _ctx: &mut Context,
+        x: f32,
+        y: f32,
+        _dx: f32,
+        _dy: f32,
+    ) -> GameResult {
+        self.pointer_moved(x, y);
+        Ok(())
+    }
+
+    /// `key_up_event` gets fired when a key is let go, which stops the padle moving
+    /// that way.
+    fn key_up_event(&mut self, _ctx: &mut Context, input: KeyInput) -> GameResult {
+        let key = input.keycode.and_then(Key::from_key_code);
+        if let (Some(key), None) = (key, &self.playback) {
+            self.key_up(key);
+        }
+
+        Ok(())
+    }
+}
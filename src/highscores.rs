@@ -0,0 +1,57 @@
+// High scores are kept in a small JSON file so they survive between sessions.
+// By default it lives in the user data directory, but every function takes the
+// path explicitly so tests can point it somewhere else.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScores {
+    /// The most points scored by both players together in a single game
+    pub best_combined: u32,
+    /// How many games each player has won
+    pub wins1: u32,
+    pub wins2: u32,
+}
+
+impl HighScores {
+    /// Takes a finished game into account.
+    pub fn record_game(&mut self, score1: u32, score2: u32, winner: u8) {
+        self.best_combined = self.best_combined.max(score1 + score2);
+        match winner {
+            1 => self.wins1 += 1,
+            _ => self.wins2 += 1,
+        }
+    }
+}
+
+/// Where the high scores are stored unless told otherwise, which is
+/// `highscores.json` in the user data directory. There might not be one on
+/// exotic systems, in which case we return `None`.
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "moving_paddles")
+        .map(|dirs| dirs.data_dir().join("highscores.json"))
+}
+
+/// Reads the high scores from `path`. A missing file simply means nobody has
+/// finished a game yet, so we start from zeros.
+pub fn load_high_scores(path: &Path) -> io::Result<HighScores> {
+    match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(io::Error::from),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HighScores::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the high scores to `path`, creating its directory if needed.
+pub fn save_high_scores(path: &Path, scores: &HighScores) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(scores)?;
+    fs::write(path, json)
+}
@@ -0,0 +1,218 @@
+// Which keys move which padle. Players can remap them in a small JSON file in the
+// user config directory, which looks like this:
+//
+//     {
+//         "player1": { "up": "W", "down": "S" },
+//         "player2": { "up": "Up", "down": "Down" }
+//     }
+//
+// Players 3 and 4 are the teammates of players 1 and 2 when the teams have two
+// padles each, and may be added the same way as "player3" and "player4".
+//
+// Keys are named the way `Key` names them, for example `A`, `Key1`, `Up` or
+// `Numpad8`. Without a file the players get W/S and the arrow keys, and their
+// teammates E/D and Numpad8/Numpad5. For a quick change there are also a few
+// `PRESETS` to pick from on the menu.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "gui")]
+use ggez::input::keyboard::KeyCode;
+use serde::Deserialize;
+
+use crate::Direction;
+
+/// Builds the `Key` type out of two lists of keys: the ones with a name, which
+/// go into a table of `(name, Key)` pairs so the names in the file are spelled
+/// exactly like the variants, and the ones the game only listens to itself.
+/// Every variant is spelled like the ggez `KeyCode` it comes from.
+macro_rules! keys {
+    (named: [$($named:ident),* $(,)?], unnamed: [$($unnamed:ident),* $(,)?] $(,)?) => {
+        /// The keys the game knows about. The library doesn't need a window, so it
+        /// has its own keys rather than the window's.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum Key {
+            $($named,)*
+            $($unnamed,)*
+        }
+
+        /// The keys that have a name, which are all the keys that can be bound to
+        /// a padle and most of the ones the game itself listens to.
+        const KEY_NAMES: &[(&str, Key)] = &[$((stringify!($named), Key::$named)),*];
+
+        #[cfg(feature = "gui")]
+        impl Key {
+            /// The key the window reports as `code`, or `None` if the game
+            /// doesn't know about it.
+            pub fn from_key_code(code: KeyCode) -> Option<Key> {
+                match code {
+                    $(KeyCode::$named => Some(Key::$named),)*
+                    $(KeyCode::$unnamed => Some(Key::$unnamed),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    named: [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        Up, Down, Left, Right, Space, Tab, Back, Home, End, PageUp, PageDown, Insert, Delete,
+        LShift, RShift, LControl, RControl, LAlt, RAlt, Comma, Escape, Return, NumpadEnter,
+    ],
+    // The function keys switch things on and off, so they are never bound and
+    // never recorded
+    unnamed: [F3, F5, F9, F12],
+}
+
+/// The keys of one player as they are written in the file.
+#[derive(Deserialize)]
+struct PlayerKeys {
+    up: String,
+    down: String,
+}
+
+/// The whole file.
+#[derive(Deserialize)]
+struct KeyBindingsFile {
+    player1: PlayerKeys,
+    player2: PlayerKeys,
+    player3: Option<PlayerKeys>,
+    player4: Option<PlayerKeys>,
+}
+
+/// The ready-made layouts, each with its name and the up and down keys of
+/// players 1 to 4. The first one is what players get without a file.
+pub const PRESETS: [(&str, [(Key, Key); 4]); 2] = [
+    (
+        "W/S and arrows",
+        [
+            (Key::W, Key::S),
+            (Key::Up, Key::Down),
+            (Key::E, Key::D),
+            (Key::Numpad8, Key::Numpad5),
+        ],
+    ),
+    (
+        "A/Z and K/M",
+        [
+            (Key::A, Key::Z),
+            (Key::K, Key::M),
+            (Key::E, Key::D),
+            (Key::Numpad8, Key::Numpad5),
+        ],
+    ),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    /// The player each bound key belongs to and the way it moves their padle
+    keys: HashMap<Key, (u8, Direction)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings::preset(0)
+    }
+}
+
+impl KeyBindings {
+    /// The bindings of the layout at `index` in `PRESETS`.
+    pub fn preset(index: usize) -> Self {
+        let mut keys = HashMap::new();
+        for (player, (up, down)) in (1..).zip(PRESETS[index].1) {
+            keys.insert(up, (player, Direction::Up));
+            keys.insert(down, (player, Direction::Down));
+        }
+        KeyBindings { keys }
+    }
+
+    /// Where these bindings are among the `PRESETS`, or `None` if they came from
+    /// a file that set up keys of its own.
+    pub fn preset_index(&self) -> Option<usize> {
+        (0..PRESETS.len()).find(|&index| *self == KeyBindings::preset(index))
+    }
+
+    /// Tells which player a key belongs to and which way it moves their padle, or
+    /// `None` if the key isn't bound.
+    pub fn get(&self, key: Key) -> Option<(u8, Direction)> {
+        self.keys.get(&key).copied()
+    }
+
+    /// Builds the bindings from the file's contents. Every key may only do one
+    /// thing, so a key bound twice, and in particular a key bound to two
+    /// players, is an error.
+    fn from_file(file: KeyBindingsFile) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        let players = [
+            (1, Some(file.player1)),
+            (2, Some(file.player2)),
+            (3, file.player3),
+            (4, file.player4),
+        ];
+        for (player, PlayerKeys { up, down }) in players
+            .into_iter()
+            .filter_map(|(player, keys)| keys.map(|keys| (player, keys)))
+        {
+            for (dir, name) in [(Direction::Up, up), (Direction::Down, down)] {
+                let key = key_from_name(&name)?;
+                match keys.insert(key, (player, dir)) {
+                    Some((other, _)) if other != player => {
+                        return Err(format!("{} is bound to more than one player", name));
+                    }
+                    Some(_) => {
+                        return Err(format!("{} is bound twice for player {}", name, player));
+                    }
+                    None => (),
+                }
+            }
+        }
+        Ok(KeyBindings { keys })
+    }
+}
+
+/// Looks up a key by its name.
+pub fn key_from_name(name: &str) -> Result<Key, String> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| *key_name == name)
+        .map(|(_, key)| *key)
+        .ok_or_else(|| format!("Unknown key {}", name))
+}
+
+/// The name of a key, or `None` for keys that don't have one.
+pub fn key_name(key: Key) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|(_, named_key)| *named_key == key)
+        .map(|(name, _)| *name)
+}
+
+/// Where the key bindings are read from, which is `keybindings.json` in the user
+/// config directory. There might not be one on exotic systems, in which case we
+/// return `None`.
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "moving_paddles")
+        .map(|dirs| dirs.config_dir().join("keybindings.json"))
+}
+
+/// Reads the key bindings from `path`. Without a file the players get the
+/// default keys, but a file that binds keys badly is rejected.
+pub fn load_key_bindings(path: &Path) -> io::Result<KeyBindings> {
+    match fs::read_to_string(path) {
+        Ok(json) => {
+            let file = serde_json::from_str(&json).map_err(io::Error::from)?;
+            KeyBindings::from_file(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KeyBindings::default()),
+        Err(e) => Err(e),
+    }
+}
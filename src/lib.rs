@@ -0,0 +1,3711 @@
+//! Moving Paddles, a game of Pong on a grid. The whole game lives in this library
+//! so it can be embedded and driven by other programs: build a `GameState` with
+//! `GameState::with_seed`, feed it keys with `key_down` and `key_up` and move it
+//! along one update at a time with `step`, which needs no window. The binary is
+//! a thin ggez front end on top of it. The drawing, the sounds and the event
+//! handling it needs come with the `gui` feature, which is on by default; build
+//! with `--no-default-features` to get only the game logic, without ggez. Keys
+//! are the game's own `keybindings::Key`s, whatever they come from.
+
+// First we'll import the crates we need for our game;
+// in this case that is just `oorandom` (and `getrandom`
+// to seed the RNG.) Everything from ggez stays in the `gui` module.
+use oorandom::Rand32;
+
+use serde::{Deserialize, Serialize};
+
+// We'll bring in some things from `std` to help us in the future.
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// The settings that can be chosen on the command line live in their own module,
+// and so do the key bindings, the saving and loading of high scores, replays and
+// unfinished matches, the sound effects, the color themes and playing over the
+// network. So does the window the game is drawn in, which is only built with the
+// `gui` feature.
+pub mod config;
+pub mod highscores;
+pub mod keybindings;
+pub mod net;
+pub mod replay;
+pub mod savegame;
+pub mod sounds;
+pub mod theme;
+#[cfg(feature = "gui")]
+mod gui;
+use config::{Config, Difficulty, ServeRule, DIFFICULTIES};
+use highscores::HighScores;
+use keybindings::{Key, KeyBindings};
+use net::{Message, Peer, Snapshot};
+use replay::Replay;
+use sounds::Sound;
+use theme::THEMES;
+
+// The first thing we want to do is set up some constants that will help us out later.
+
+// Here we define the default size of our game board in terms of how many grid
+// cells it will take up. We choose to make a 30 x 20 game board.
+const GRID_SIZE: (i16, i16) = (30, 20);
+// Now we define the default pixel size of each tile, which we make 32x32 pixels.
+// Both can be changed from the command line, see `Config`.
+const GRID_CELL_SIZE: (i16, i16) = (32, 32);
+
+// Here we're defining how often we want our game to update. This will be
+// important later so that we don't have our snake fly across the screen because
+// it's moving a full tile every frame. This is only the default rate, see
+// `Config`, but all the speeds and durations below are measured in updates at
+// this rate and get scaled to whatever rate the game actually runs at.
+pub const DESIRED_FPS: u32 = 23;
+
+// The first player to reach this many points wins the game, as in classic Pong.
+const WINNING_SCORE: u32 = 11;
+
+// A match is played over several games, and the first player to win this many of
+// them wins the match. Two makes it best of three.
+const ROUNDS_TO_WIN: u32 = 2;
+
+// Debugging aids such as the paddle coordinate readout are only available in
+// debug builds.
+const DEBUG_TOOLS: bool = cfg!(debug_assertions);
+
+// Before every serve the game counts down from three, so the ball stays put
+// for this many updates.
+const SERVE_COUNTDOWN_TICKS: u32 = 3 * DESIRED_FPS;
+
+// The rows a serve climbs or drops for every column it crosses, one of which is
+// picked at random. A flat serve makes for the same rally over and over, so it
+// isn't one of them. The ball always crosses a column per step, so however this
+// is tuned it never goes straight up or down.
+const SERVE_SLOPES: [i16; 2] = [-1, 1];
+
+// Every time a padle hits the ball it gets a bit faster, measured in cells per
+// update, until it reaches the maximum speed. The players may pick another
+// maximum, and this is the one they get if they don't.
+const BALL_START_SPEED: f32 = 1.0;
+const BALL_SPEED_UP: f32 = 0.1;
+const MAX_BALL_SPEED: f32 = 2.0;
+
+// When the catch net is switched on, this is how many updates the ball rests
+// on the center column before it carries on.
+const NET_PAUSE_TICKS: u32 = 6;
+
+// A power-up turns up on the court about once every ten seconds. The ball picks it
+// up by passing over it, and the next padle to hit the ball then grows or shrinks
+// by a couple of segments for five seconds.
+const POWER_UP_CHANCE: u32 = 10 * DESIRED_FPS;
+const POWER_UP_TICKS: u32 = 5 * DESIRED_FPS;
+const POWER_UP_SIZE_CHANGE: i16 = 2;
+
+// A padle remembers at most this many key presses it hasn't acted on yet, so
+// quick taps between two updates each move it, but mashing a key doesn't send
+// it off on its own for ages afterwards.
+const INPUT_QUEUE_LENGTH: usize = 3;
+
+// When the ball hits a padle the screen shakes for a moment, starting out this
+// many pixels to either side and calming down over this many updates.
+const SHAKE_STRENGTH: f32 = 6.0;
+const SHAKE_TICKS: u32 = 6;
+
+// When a point is scored, the half of the board of the player who won it lights
+// up in their color, fading out over this many updates.
+const FLASH_TICKS: u32 = 8;
+
+// A padle that hits the ball lights up for this many updates.
+const HIGHLIGHT_TICKS: u32 = 4;
+
+// After this many updates on the menu without a key being pressed, the game
+// starts playing against itself to show what it looks like.
+const DEMO_IDLE_TICKS: u32 = 15 * DESIRED_FPS;
+
+// The ball leaves a fading trail of the last few cells it went through.
+const TRAIL_LENGTH: usize = 5;
+
+// With slow motion on, a point that could decide the match is played at this
+// fraction of the usual speed.
+const SLOW_MOTION_FACTOR: f32 = 0.4;
+
+// How many random cells we try for a new ball before giving up and putting it in
+// the middle.
+const BALL_SPAWN_ATTEMPTS: u32 = 100;
+
+// A padle hitting the ball sends a burst of sparks flying from where it hit. How
+// many there are per hit, how many pixels they fly per update at `DESIRED_FPS`,
+// how many updates they last, and how many can be around at once before the
+// oldest make room.
+const PARTICLES_PER_HIT: usize = 8;
+const PARTICLE_SPEED: f32 = 6.0;
+const PARTICLE_LIFE: f32 = 10.0;
+const MAX_PARTICLES: usize = 64;
+
+/// Now we define a struct that will hold an entity's position on our game board
+/// or grid which we defined above. We'll use signed integers because we only want
+/// to store whole numbers, and we need them to be signed so that they work properly
+/// with our modulus arithmetic later.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct GridPosition {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl GridPosition {
+    /// We make a standard helper function so that we can create a new `GridPosition`
+    /// more easily.
+    pub fn new(x: i16, y: i16) -> Self {
+        GridPosition { x, y }
+    }
+
+    /// As well as a helper function that will give us a random `GridPosition` from
+    /// `(0, 0)` to `(max_x, max_y)`
+    pub fn random(rng: &mut Rand32, max_x: i16, max_y: i16) -> Self {
+        // We can use `.into()` to convert from `(i16, i16)` to a `GridPosition` since
+        // we implement `From<(i16, i16)>` for `GridPosition` below.
+        (
+            rng.rand_range(0..(max_x as u32)) as i16,
+            rng.rand_range(0..(max_y as u32)) as i16,
+        )
+            .into()
+    }
+
+    /// We'll make another helper function that takes one grid position and returns a new one after
+    /// making one move in the direction of `dir`. What happens at the edge of the board depends
+    /// on `edges`: either the position wraps around to the other side, or it stays put.
+    /// For wrapping we use the [`rem_euclid()`](https://doc.rust-lang.org/std/primitive.i16.html#method.rem_euclid)
+    /// API when crossing the top/left limits, as the standard remainder function (`%`) returns a
+    /// negative value when the left operand is negative.
+    /// Only the Up/Left cases require rem_euclid(); for consistency, it's used for all of them.
+    pub fn new_from_move(
+        pos: GridPosition,
+        dir: Direction,
+        grid_size: (i16, i16),
+        edges: EdgeMode,
+    ) -> Self {
+        let (x, y) = match dir {
+            Direction::None => return pos,
+            Direction::Up => (pos.x, pos.y - 1),
+            Direction::Down => (pos.x, pos.y + 1),
+            Direction::Left => (pos.x - 1, pos.y),
+            Direction::Right => (pos.x + 1, pos.y),
+        };
+        match edges {
+            EdgeMode::Wrap => {
+                GridPosition::new(x.rem_euclid(grid_size.0), y.rem_euclid(grid_size.1))
+            }
+            EdgeMode::Clamp => GridPosition::new(
+                x.clamp(0, grid_size.0 - 1),
+                y.clamp(0, grid_size.1 - 1),
+            ),
+        }
+    }
+}
+
+/// What a move off the edge of the board does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeMode {
+    /// Come back in on the opposite side. Nothing in regular Pong wraps, but
+    /// game variants can.
+    Wrap,
+    /// Stay on the last cell before the edge
+    Clamp,
+}
+
+/// And here we implement `From` again to allow us to easily convert between
+/// `(i16, i16)` and a `GridPosition`.
+impl From<(i16, i16)> for GridPosition {
+    fn from(pos: (i16, i16)) -> Self {
+        GridPosition { x: pos.0, y: pos.1 }
+    }
+}
+
+/// Next we create an enum that will represent all the possible
+/// directions that our snake could move.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    None,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// The ball needs to be able to move diagonally, which a single `Direction` can't
+/// express, so it carries a velocity instead: how many cells it moves along each
+/// axis every update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Velocity {
+    dx: i16,
+    dy: i16,
+}
+
+impl Velocity {
+    pub fn new(dx: i16, dy: i16) -> Self {
+        Velocity { dx, dy }
+    }
+}
+
+/// A `Direction` converts into the velocity of moving one cell that way, so code
+/// that thinks in directions can still set the ball going.
+impl From<Direction> for Velocity {
+    fn from(dir: Direction) -> Self {
+        match dir {
+            Direction::None => Velocity::new(0, 0),
+            Direction::Up => Velocity::new(0, -1),
+            Direction::Down => Velocity::new(0, 1),
+            Direction::Left => Velocity::new(-1, 0),
+            Direction::Right => Velocity::new(1, 0),
+        }
+    }
+}
+
+/// This is mostly just a semantic abstraction over a `GridPosition` to represent
+/// a segment of the padle, along with the color it is drawn in.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Segment {
+    pos: GridPosition,
+    color: [f32; 4],
+}
+
+impl Segment {
+    pub fn new(pos: GridPosition, color: [f32; 4]) -> Self {
+        Segment { pos, color }
+    }
+}
+
+/// This is again an abstraction over a `GridPosition` that represents
+/// a ball the paddle can beat. It can draw itself.
+#[derive(Serialize, Deserialize)]
+pub struct Ball {
+    pos: GridPosition,
+    /// How many cells wide and high the ball is. `pos` is its top left cell.
+    size: i16,
+    /// Then we have the current velocity of the ball. This is how far
+    /// it will move when `update` is called on it.
+    velocity: Velocity,
+    /// How many steps of `velocity` the ball takes per update. This starts at one
+    /// and grows during a rally, so it is usually fractional.
+    speed: f32,
+    /// The fraction of a step the ball has built up but not taken yet
+    progress: f32,
+    /// Whether the ball stops for a beat when it reaches the center column
+    catches_at_net: bool,
+    /// How much longer the ball stays put at the net, in updates at `DESIRED_FPS`
+    net_pause: f32,
+    /// The horizontal velocity the ball had the last time the net stopped it,
+    /// so it only gets caught once per crossing direction
+    last_net_catch: i16,
+    /// The power-up the ball picked up, which it hands to the next padle it hits
+    power_up: Option<PowerUpKind>,
+    /// The cells the ball went through last, newest first, at most `TRAIL_LENGTH`
+    trail: VecDeque<GridPosition>,
+}
+
+/// What happened to a ball during an update, so the game can react to it. When
+/// several things happened, the ball reports the one that matters most, which is
+/// the one declared last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum BallEvent {
+    None,
+    /// The ball bounced off a wall or an obstacle
+    BouncedWall,
+    /// The padle of the given player hit the ball, and which of their team's
+    /// padles it was
+    HitPaddle(u8, usize),
+    /// The ball got past the padle of the given player
+    ScoredOn(u8),
+}
+
+impl BallEvent {
+    /// The sound that goes with the event, if any.
+    pub fn sound(self) -> Option<Sound> {
+        match self {
+            BallEvent::None => None,
+            BallEvent::BouncedWall => Some(Sound::WallBounce),
+            BallEvent::HitPaddle(..) => Some(Sound::PaddleHit),
+            BallEvent::ScoredOn(_) => Some(Sound::Score),
+        }
+    }
+}
+
+impl Ball {
+    pub fn new(pos: GridPosition) -> Self {
+        Ball { 
+            pos,
+            size: 1,
+            velocity: Direction::Left.into(),
+            speed: BALL_START_SPEED,
+            progress: 0.0,
+            catches_at_net: false,
+            net_pause: 0.0,
+            last_net_catch: 0,
+            power_up: None,
+            trail: VecDeque::with_capacity(TRAIL_LENGTH),
+        }
+    }
+
+    /// Builds a ball as big as `config` says at `pos`, moved up and left as far
+    /// as it takes to fit on the board.
+    pub fn sized(pos: GridPosition, config: &Config) -> Self {
+        let size = config.ball_size;
+        let (width, height) = config.grid_size;
+        let mut ball = Ball::new(GridPosition::new(
+            pos.x.min(width - size).max(0),
+            pos.y.min(height - size).max(0),
+        ));
+        ball.size = size;
+        ball
+    }
+
+    /// The cell the ball is in, or its top left cell if it covers several.
+    pub fn pos(&self) -> GridPosition {
+        self.pos
+    }
+
+    /// Tells whether the ball would cover `cell` if its top left cell were `pos`.
+    fn covers_at(&self, pos: GridPosition, cell: GridPosition) -> bool {
+        (pos.x..pos.x + self.size).contains(&cell.x) && (pos.y..pos.y + self.size).contains(&cell.y)
+    }
+
+    /// Tells whether the ball covers `cell`.
+    fn covers(&self, cell: GridPosition) -> bool {
+        self.covers_at(self.pos, cell)
+    }
+
+    /// Sends the ball off at one of the `SERVE_SLOPES` towards `receiver`, or
+    /// towards a randomly chosen player if there is none. It always heads for one
+    /// of the players, never just up or down.
+    pub fn serve(&mut self, rng: &mut Rand32, receiver: Option<u8>) {
+        let receiver = receiver.unwrap_or_else(|| rng.rand_range(1..3) as u8);
+        let dir = if receiver == 1 {
+            Direction::Left
+        } else {
+            Direction::Right
+        };
+        self.velocity = dir.into();
+        self.velocity.dy = SERVE_SLOPES[rng.rand_range(0..SERVE_SLOPES.len() as u32) as usize];
+    }
+
+    /// The main update function for our ball which gets called every time
+    /// we want to update the game state. A fast ball takes several single steps
+    /// in one update so that it can never jump over a padle. It tells what
+    /// happened to the ball along the way. `padles` holds the padles of player 1
+    /// and of player 2. Without any padles for player 2 the right wall sends the
+    /// ball back instead, for practicing alone.
+    ///
+    /// `elapsed` is how much time the update covers, counted in updates at
+    /// `DESIRED_FPS`. That is `config.tick_scale()` for a fixed update, or the
+    /// length of the last frame in continuous mode.
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &mut self,
+        padles: [&[Padle]; 2],
+        obstacles: &[GridPosition],
+        heat_map: &mut HeatMap,
+        power_up: &mut Option<PowerUp>,
+        elapsed: f32,
+        config: &Config,
+    ) -> BallEvent {
+        // While the net is holding the ball we just wait it out.
+        if self.net_pause > 0.0 {
+            self.net_pause -= elapsed;
+            heat_map.record(self.pos);
+            return BallEvent::None;
+        }
+
+        let mut event = BallEvent::None;
+
+        // The players may have lowered the maximum speed since the ball got this
+        // fast, so it slows down to it.
+        self.speed = self.speed.min(config.max_ball_speed);
+
+        // The speed is measured in steps per update at `DESIRED_FPS`, so an update
+        // covering more or less time than that is worth more or less of it. The
+        // players may also have sped every ball up or slowed it down in the
+        // settings.
+        self.progress += self.speed * config.ball_speed * elapsed;
+        while self.progress >= 1.0 {
+            self.progress -= 1.0;
+            let before = self.pos;
+            event = event.max(self.step(padles, obstacles, config));
+            self.extend_trail(before);
+
+            // Once the ball got past a padle it has left the board, so any leftover
+            // movement is dropped.
+            if self.is_out(config) {
+                self.progress = 0.0;
+                let player = if self.pos.x < 0 { 1 } else { 2 };
+                return BallEvent::ScoredOn(player);
+            }
+
+            // We remember that the ball has visited the cell it ended up in, and
+            // pick up the power-up lying there, if any.
+            heat_map.record(self.pos);
+            if power_up.is_some_and(|power_up| self.covers(power_up.pos)) {
+                self.power_up = power_up.take().map(|power_up| power_up.kind);
+            }
+
+            // If the net holds the ball, any leftover movement is dropped as well.
+            if self.net_pause > 0.0 {
+                self.progress = 0.0;
+                break;
+            }
+        }
+
+        event
+    }
+
+    /// Moves the ball a single step along its velocity, bouncing it off whatever
+    /// it runs into, and tells what it ran into.
+    fn step(
+        &mut self,
+        padles: [&[Padle]; 2],
+        obstacles: &[GridPosition],
+        config: &Config,
+    ) -> BallEvent {
+        // A ball left sitting on a padle's column may have had the padle move in
+        // front of it since the last step, and then it still gets hit.
+        if let Some(event) = self.hit_padles(padles, config) {
+            return event;
+        }
+
+        let grid_size = config.grid_size;
+        let mut event = BallEvent::None;
+        let before = self.pos;
+        let after = GridPosition::new(
+            self.pos.x + self.velocity.dx,
+            self.pos.y + self.velocity.dy,
+        );
+
+        // On its way the ball sweeps past more than just the cell it ends up in:
+        // moving diagonally it crosses the corners of the cells beside its path.
+        // A padle standing in any of them hits the ball there, rather than letting
+        // it slip through the gap.
+        for cell in swept_path(before, after) {
+            if cell == after {
+                break;
+            }
+            self.pos = cell;
+            if let Some(event) = self.hit_padles(padles, config) {
+                return event;
+            }
+        }
+        let (pos, velocity, bounced) = bounce_off_walls(after, self.velocity, self.size, config);
+        self.pos = pos;
+        self.velocity = velocity;
+        if bounced {
+            event = BallEvent::BouncedWall;
+        }
+
+        // With nobody on the right, the right wall reflects the ball just like the
+        // top and bottom ones do.
+        if padles[1].is_empty() && self.pos.x >= grid_size.0 - self.size && self.velocity.dx > 0 {
+            self.pos.x = 2 * (grid_size.0 - self.size) - self.pos.x;
+            self.velocity.dx = -self.velocity.dx;
+            event = BallEvent::BouncedWall;
+        }
+
+        // An obstacle sends the ball back to where it came from, turned around along
+        // whichever axis it ran into the obstacle. If it only clipped a corner it
+        // is turned around along both.
+        let blocked = |pos: GridPosition| obstacles.iter().any(|&cell| self.covers_at(pos, cell));
+        if blocked(self.pos) {
+            let blocks_x = blocked(GridPosition::new(self.pos.x, before.y));
+            let blocks_y = blocked(GridPosition::new(before.x, self.pos.y));
+            if blocks_x || !blocks_y {
+                self.velocity.dx = -self.velocity.dx;
+            }
+            if blocks_y || !blocks_x {
+                self.velocity.dy = -self.velocity.dy;
+            }
+            self.pos = before;
+            event = BallEvent::BouncedWall;
+        }
+
+        // If the net is on and the ball just reached the center column, we hold it
+        // there for a moment, unless it was already caught going this way.
+        if self.catches_at_net
+            && self.pos.x == grid_size.0 / 2
+            && self.last_net_catch != self.velocity.dx
+        {
+            self.last_net_catch = self.velocity.dx;
+            self.net_pause = NET_PAUSE_TICKS as f32;
+        }
+
+        // Padles are checked last, once the ball is where it's going to be, and
+        // before anybody asks whether it got out.
+        self.hit_padles(padles, config).unwrap_or(event)
+    }
+
+    /// A padle sends the ball back the way it came, horizontally off an upright
+    /// padle and vertically off a flat one, and a little faster than before. The
+    /// ball bounces at most once per step and ends up just in front of the padle
+    /// it hit, so it can never be turned around twice and slip through. Where on
+    /// the padle it hit decides the spin it gets. Tells which padle hit the ball,
+    /// if any did.
+    fn hit_padles(&mut self, padles: [&[Padle]; 2], config: &Config) -> Option<BallEvent> {
+        let hit = (1..)
+            .zip(padles)
+            .flat_map(|(player, team)| team.iter().enumerate().map(move |team| (player, team)))
+            .find_map(|(player, (member, padle))| {
+                padle.meats_ball(self).map(|index| (player, member, padle, index))
+            });
+        if let Some((player, member, padle, index)) = hit {
+            let spin = padle.spin_at(index);
+            let center = padle.center();
+            // The ball ends up just in front of the padle, whichever way it faces
+            match padle.orientation {
+                Orientation::Vertical => {
+                    self.velocity.dx = -self.velocity.dx;
+                    self.velocity.dy = (self.velocity.dy + spin).clamp(-1, 1);
+                    self.pos.x = if self.velocity.dx > 0 {
+                        center.x + 1
+                    } else {
+                        center.x - self.size
+                    };
+                }
+                Orientation::Horizontal => {
+                    self.velocity.dy = -self.velocity.dy;
+                    self.velocity.dx = (self.velocity.dx + spin).clamp(-1, 1);
+                    self.pos.y = if self.velocity.dy > 0 {
+                        center.y + 1
+                    } else {
+                        center.y - self.size
+                    };
+                }
+            }
+            self.speed_up(config);
+            return Some(BallEvent::HitPaddle(player, member));
+        }
+
+        None
+    }
+
+    /// Adds the cell the ball just left to the front of its trail, dropping the
+    /// oldest one once the trail is full. If the ball didn't end up next to that
+    /// cell it jumped somewhere, and the old trail would draw a streak across the
+    /// board that the ball never flew along, so we start the trail over.
+    fn extend_trail(&mut self, left: GridPosition) {
+        if (self.pos.x - left.x).abs() > 1 || (self.pos.y - left.y).abs() > 1 {
+            self.trail.clear();
+            return;
+        }
+        if self.trail.len() == TRAIL_LENGTH {
+            self.trail.pop_back();
+        }
+        self.trail.push_front(left);
+    }
+
+    /// Makes the ball a little faster, but never faster than `config` allows. It
+    /// still moves a single cell per step, however fast it goes, so it can't
+    /// skip over a padle.
+    fn speed_up(&mut self, config: &Config) {
+        self.speed = (self.speed + BALL_SPEED_UP).min(config.max_ball_speed);
+    }
+
+    /// The padles stand on the first and the last column of the board, and just
+    /// outside of those are the score planes. A ball on a padle's column can still
+    /// be hit, and only once it crossed the plane behind the padle is it out.
+    pub fn is_out(&self, config: &Config) -> bool {
+        self.pos.x < 0 || self.pos.x + self.size > config.grid_size.0
+    }
+
+    /// Where the ball will be `ticks` updates at `DESIRED_FPS` from now, if the top
+    /// and bottom walls are all it bounces off. Padles, obstacles and the net are
+    /// left out, and once the ball would leave the board we stop at the last cell
+    /// it had on it.
+    pub fn predicted_pos(&self, ticks: u32, config: &Config) -> GridPosition {
+        let (mut pos, mut velocity) = (self.pos, self.velocity);
+        let mut progress = self.progress;
+        for _ in 0..ticks {
+            progress += self.speed * config.ball_speed;
+            while progress >= 1.0 {
+                progress -= 1.0;
+                let after = GridPosition::new(pos.x + velocity.dx, pos.y + velocity.dy);
+                let (next, next_velocity, _) = bounce_off_walls(after, velocity, self.size, config);
+                if next.x < 0 || next.x + self.size > config.grid_size.0 {
+                    return pos;
+                }
+                (pos, velocity) = (next, next_velocity);
+            }
+        }
+        pos
+    }
+
+    /// The point in the middle of the ball on screen, in pixels.
+    fn center_on_screen(&self, config: &Config) -> (f32, f32) {
+        let size = self.size as f32;
+        let middle = |cell: i16, cell_size: i16| (cell as f32 + size / 2.0) * cell_size as f32;
+        (middle(self.pos.x, config.cell_size.0), middle(self.pos.y, config.cell_size.1))
+    }
+}
+
+/// A heat map counts how many ticks the ball has spent in each grid cell over
+/// a match, so we can see afterwards where the play actually happened.
+#[derive(Default)]
+struct HeatMap {
+    /// One counter per grid cell, stored row by row.
+    cells: Vec<u32>,
+    /// The size of the grid the counters cover
+    grid_size: (i16, i16),
+}
+
+impl HeatMap {
+    pub fn new(grid_size: (i16, i16)) -> Self {
+        HeatMap {
+            cells: vec![0; grid_size.0 as usize * grid_size.1 as usize],
+            grid_size,
+        }
+    }
+
+    /// Turns a `GridPosition` into an index into `cells`.
+    fn index(&self, pos: GridPosition) -> usize {
+        pos.y as usize * self.grid_size.0 as usize + pos.x as usize
+    }
+
+    /// Bumps the counter of the cell at `pos`.
+    pub fn record(&mut self, pos: GridPosition) {
+        let index = self.index(pos);
+        self.cells[index] = self.cells[index].saturating_add(1);
+    }
+}
+
+/// The kinds of power-up, named after what they do to the padle that gets them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum PowerUpKind {
+    Grow,
+    Shrink,
+}
+
+/// A power-up waiting on the court for the ball to pass over it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct PowerUp {
+    pos: GridPosition,
+    kind: PowerUpKind,
+}
+
+impl PowerUp {
+    /// Puts a power-up of a random kind somewhere on the court, but never in the
+    /// padles' columns where the ball can't reach it.
+    pub fn random(rng: &mut Rand32, config: &Config) -> Self {
+        let (width, height) = config.grid_size;
+        let pos = GridPosition::random(rng, width - 2, height);
+        let kind = if rng.rand_range(0..2) == 0 {
+            PowerUpKind::Grow
+        } else {
+            PowerUpKind::Shrink
+        };
+        PowerUp {
+            pos: GridPosition::new(pos.x + 1, pos.y),
+            kind,
+        }
+    }
+}
+
+/// A spark flying off where a padle hit the ball. Unlike everything else on the
+/// board it isn't tied to the grid, so its position and velocity are in pixels.
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    pos: (f32, f32),
+    vel: (f32, f32),
+    /// How many more updates at `DESIRED_FPS` the spark is around for
+    life: f32,
+}
+
+impl Particle {
+    /// Moves the spark along by `elapsed` updates at `DESIRED_FPS`, slowly
+    /// burning it out.
+    fn update(&mut self, elapsed: f32) {
+        self.pos.0 += self.vel.0 * elapsed;
+        self.pos.1 += self.vel.1 * elapsed;
+        self.life -= elapsed;
+    }
+}
+
+/// A burst of `PARTICLES_PER_HIT` sparks flying from `at`, spread out over a
+/// fan heading left for a negative `heading` and right otherwise, each a little
+/// faster or slower than the last.
+fn particle_burst(rng: &mut Rand32, at: (f32, f32), heading: i16) -> Vec<Particle> {
+    let side = if heading < 0 { -1.0 } else { 1.0 };
+    (0..PARTICLES_PER_HIT)
+        .map(|_| {
+            let angle = (rng.rand_float() - 0.5) * std::f32::consts::FRAC_PI_2 * 1.5;
+            let speed = PARTICLE_SPEED * (0.5 + rng.rand_float());
+            Particle {
+                pos: at,
+                vel: (angle.cos() * speed * side, angle.sin() * speed),
+                life: PARTICLE_LIFE,
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Padle {
+    /// Next we have the body, which we choose to represent as a `VecDeque`
+    /// of `Segment`s.
+    body: VecDeque<Segment>,
+    /// Then we have the current direction the padle is moving. This is
+    /// the direction it will move every time `update` is called on it.
+    dir: Direction,
+    /// The directions whose keys or buttons are held down right now, in the
+    /// order they were pressed. The most recent one decides `dir`.
+    held: Vec<Direction>,
+    /// The presses that came in since the last update, oldest first. Each of
+    /// them is worth a step of its own, even if the key was let go again already.
+    queued: VecDeque<Direction>,
+    /// How many segments the padle has when no power-up is changing it
+    normal_length: i16,
+    /// How many more updates a power-up keeps the padle's length changed
+    power_up_timer: u32,
+    /// How many cells the padle moves per update at `DESIRED_FPS`
+    speed: f32,
+    /// The fraction of a step the padle has built up but not taken yet
+    progress: f32,
+    /// The colors at the bottom and the top of the padle
+    colors: ([f32; 4], [f32; 4]),
+    /// Which way the padle lies, and so which way it can move
+    orientation: Orientation,
+    /// How many more updates the padle stays lit up after hitting the ball
+    highlight_timer: u32,
+    /// The first and last cell along the edge the padle may cover, when it
+    /// shares the edge with teammates, or `None` when the whole edge is its own
+    lane: Option<(i16, i16)>,
+}
+
+/// Which way a padle lies. The usual padles stand upright along the left and
+/// right edges and move up and down. Padles guarding the top or bottom edge lie
+/// flat and move left and right instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Orientation {
+    Vertical,
+    Horizontal,
+}
+
+impl Orientation {
+    /// The coordinate of `pos` along the padle, which is the one it moves along.
+    fn along(self, pos: GridPosition) -> i16 {
+        match self {
+            Orientation::Vertical => pos.y,
+            Orientation::Horizontal => pos.x,
+        }
+    }
+
+    /// The other coordinate of `pos`, which stays the same while the padle moves.
+    fn across(self, pos: GridPosition) -> i16 {
+        match self {
+            Orientation::Vertical => pos.x,
+            Orientation::Horizontal => pos.y,
+        }
+    }
+
+    /// The cell at `along` on the padle whose other coordinate stays `across`.
+    fn cell(self, across: i16, along: i16) -> GridPosition {
+        match self {
+            Orientation::Vertical => GridPosition::new(across, along),
+            Orientation::Horizontal => GridPosition::new(along, across),
+        }
+    }
+
+    /// How many cells the board has along the padle.
+    fn extent(self, config: &Config) -> i16 {
+        self.extent_of(config.grid_size)
+    }
+
+    /// How many cells a board of `grid_size` has along the padle.
+    fn extent_of(self, grid_size: (i16, i16)) -> i16 {
+        match self {
+            Orientation::Vertical => grid_size.1,
+            Orientation::Horizontal => grid_size.0,
+        }
+    }
+
+    /// The directions the padle can move in: first the one towards the lower
+    /// coordinates, then the one towards the higher ones.
+    fn directions(self) -> (Direction, Direction) {
+        match self {
+            Orientation::Vertical => (Direction::Up, Direction::Down),
+            Orientation::Horizontal => (Direction::Left, Direction::Right),
+        }
+    }
+}
+
+impl Padle {
+    /// Builds a padle of `length` segments (at least one) standing upwards from
+    /// `pos` on a board of `grid_size`, that moves `speed` cells per update. If
+    /// that would poke out of the top of the board, the whole padle is moved
+    /// down until it fits.
+    pub fn new(pos: GridPosition, length: i16, speed: f32, grid_size: (i16, i16)) -> Self {
+        Padle::with_orientation(pos, length, speed, Orientation::Vertical, grid_size)
+    }
+
+    /// Builds a padle like `new` does, lying the way `orientation` says. A flat
+    /// padle reaches leftwards from `pos` and is moved right until it fits.
+    ///
+    /// A padle needs at least one segment, and can't be longer than the board of
+    /// `grid_size` is along it, so any other `length` is quietly brought into
+    /// that range. The padle's `length` tells what it ended up with.
+    pub fn with_orientation(
+        pos: GridPosition,
+        length: i16,
+        speed: f32,
+        orientation: Orientation,
+        grid_size: (i16, i16),
+    ) -> Self {
+        let length = length.clamp(1, orientation.extent_of(grid_size));
+        let across = orientation.across(pos);
+        let start = (orientation.along(pos) - (length - 1)).max(0);
+        let end = start + length - 1;
+        let mut body = VecDeque::new();
+
+        let colors = (THEMES[0].players[0], THEMES[0].padle_top);
+        for seg_number in 0..length {
+            body.push_back(Segment::new(orientation.cell(across, end - seg_number), colors.0));
+        }
+
+        let mut padle = Padle {
+            orientation,
+            highlight_timer: 0,
+            lane: None,
+            body,
+            dir: Direction::None,
+            held: Vec::new(),
+            queued: VecDeque::new(),
+            normal_length: length,
+            power_up_timer: 0,
+            speed,
+            progress: 0.0,
+            colors,
+        };
+        padle.paint();
+        padle
+    }
+
+    /// The first and last cell along the edge the padle may cover. That is the
+    /// whole edge unless the padle was given a lane of its own.
+    fn lane(&self, config: &Config) -> (i16, i16) {
+        self.lane.unwrap_or((0, self.orientation.extent(config) - 1))
+    }
+
+    /// Keeps the padle to the cells from `first` to `last` along its edge, moving
+    /// it to the middle of them and shortening it if it doesn't fit.
+    pub fn keep_to_lane(&mut self, first: i16, last: i16, config: &Config) {
+        self.lane = Some((first, last));
+        self.rebuild((first + last) / 2, self.length(), config);
+    }
+
+    /// Tells whether the cell `along` the edge is in the padle's lane.
+    fn lane_contains(&self, along: i16, config: &Config) -> bool {
+        let (first, last) = self.lane(config);
+        (first..=last).contains(&along)
+    }
+
+    /// Lights the padle up for a moment, after it hit the ball.
+    fn highlight(&mut self, config: &Config) {
+        self.highlight_timer = config.ticks(HIGHLIGHT_TICKS);
+    }
+
+    /// Changes the colors the padle fades between, from the bottom to the top.
+    pub fn set_colors(&mut self, bottom: [f32; 4], top: [f32; 4]) {
+        self.colors = (bottom, top);
+        self.paint();
+    }
+
+    /// Called when a key or button for `dir` goes down. The padle keeps moving
+    /// that way until it is released. Holding the key repeats the press, which
+    /// changes nothing.
+    pub fn press(&mut self, dir: Direction) {
+        if !self.held.contains(&dir) {
+            self.held.push(dir);
+            if self.queued.len() < INPUT_QUEUE_LENGTH {
+                self.queued.push_back(dir);
+            }
+        }
+        self.dir = dir;
+    }
+
+    /// Called when a key or button for `dir` is let go. If the other direction is
+    /// still held the padle goes back to moving that way, otherwise it stops.
+    pub fn release(&mut self, dir: Direction) {
+        self.held.retain(|held| *held != dir);
+        self.dir = self.held.last().copied().unwrap_or(Direction::None);
+    }
+
+    /// Forgets every held key and stops the padle.
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.queued.clear();
+        self.dir = Direction::None;
+    }
+
+    /// The main update function for our padle which gets called every time
+    /// we want to update the game state.
+    fn update(&mut self, config: &Config) {
+        self.highlight_timer = self.highlight_timer.saturating_sub(1);
+
+        // When a power-up wears off the padle goes back to its normal length.
+        if self.power_up_timer > 0 {
+            self.power_up_timer -= 1;
+            if self.power_up_timer == 0 {
+                self.resize(self.normal_length, config);
+            }
+        }
+
+        // A press that hasn't been acted on yet gets a step of its own this
+        // update, so a tap moves the padle even when it was let go right away.
+        if let Some(dir) = self.queued.pop_front() {
+            self.progress = 0.0;
+            self.step(dir, config);
+            return;
+        }
+
+        // A padle that isn't moving doesn't build up any steps.
+        if self.dir == Direction::None {
+            self.progress = 0.0;
+            return;
+        }
+
+        // Just like the ball, the padle's speed is measured per update at
+        // `DESIRED_FPS`, so it may take a step only every so often, or several.
+        // Each step stops at the walls on its own.
+        self.progress += self.speed * config.tick_scale();
+        while self.progress >= 1.0 {
+            self.progress -= 1.0;
+            self.step(self.dir, config);
+        }
+    }
+
+    /// Moves the padle a single cell in `dir`.
+    fn step(&mut self, dir: Direction, config: &Config) {
+        // A padle pushed against a wall just stays where it is.
+        if !self.can_move(dir, config) {
+            return;
+        }
+
+        let (backwards, forwards) = self.orientation.directions();
+        if dir == backwards {
+            if let Some(back) = self.body.back() {
+                let new_back_pos = GridPosition::new_from_move(
+                    back.pos,
+                    dir,
+                    config.grid_size,
+                    EdgeMode::Clamp,
+                );
+                let new_back = Segment::new(new_back_pos, back.color);
+                self.body.push_back(new_back);
+                self.body.pop_front();
+            }
+        }
+        if dir == forwards {
+            if let Some(front) = self.body.front() {
+                let new_front_pos = GridPosition::new_from_move(
+                    front.pos,
+                    dir,
+                    config.grid_size,
+                    EdgeMode::Clamp,
+                );
+                let new_front = Segment::new(new_front_pos, front.color);
+                self.body.push_front(new_front);
+                self.body.pop_back();
+            }
+        }
+
+        // Every segment has moved one place along the body, so we paint them
+        // again to keep the gradient where it was.
+        self.paint();
+    }
+
+    /// Colors the segments from the bottom of the padle to the top.
+    fn paint(&mut self) {
+        let length = self.body.len();
+        for (index, seg) in self.body.iter_mut().enumerate() {
+            seg.color = gradient_color(index, length, self.colors);
+        }
+    }
+
+    /// The position of the middle segment of the padle.
+    pub fn center(&self) -> GridPosition {
+        self.body[self.body.len() / 2].pos
+    }
+
+    /// How many segments the padle has.
+    pub fn length(&self) -> i16 {
+        self.body.len() as i16
+    }
+
+    /// The cells the padle covers, from the bottom end to the top end.
+    pub fn cells(&self) -> impl Iterator<Item = GridPosition> + '_ {
+        self.body.iter().map(|seg| seg.pos)
+    }
+
+    /// Moves the padle straight to where its center is on row `y`, or as close to
+    /// it as the board allows. Used when the padle follows the mouse.
+    pub fn set_center_y(&mut self, y: i16, config: &Config) {
+        self.rebuild(y, self.length(), config);
+    }
+
+    /// Grows or shrinks the padle for a while, depending on the power-up it got.
+    /// It never shrinks below a single segment.
+    fn apply_power_up(&mut self, kind: PowerUpKind, config: &Config) {
+        let length = match kind {
+            PowerUpKind::Grow => self.normal_length + POWER_UP_SIZE_CHANGE,
+            PowerUpKind::Shrink => (self.normal_length - POWER_UP_SIZE_CHANGE).max(1),
+        };
+        self.resize(length, config);
+        self.power_up_timer = config.ticks(POWER_UP_TICKS);
+    }
+
+    /// Changes the padle to `length` segments, keeping its center where it is.
+    fn resize(&mut self, length: i16, config: &Config) {
+        self.rebuild(self.orientation.along(self.center()), length, config);
+    }
+
+    /// Lays the padle out anew as `length` segments around `center`, counted
+    /// along the padle, as close to it as the board allows. A padle can't be
+    /// longer than the board is along it, or than its lane.
+    fn rebuild(&mut self, center: i16, length: i16, config: &Config) {
+        let orientation = self.orientation;
+        let (first, last) = self.lane(config);
+        let length = length.min(last - first + 1);
+        let across = orientation.across(self.center());
+        let end = (center + length / 2).clamp(first + length - 1, last);
+        self.body = (0..length)
+            .map(|seg_number| {
+                Segment::new(orientation.cell(across, end - seg_number), self.colors.0)
+            })
+            .collect();
+        self.paint();
+    }
+
+    /// Tells whether the padle can take a step in `dir` without leaving the board,
+    /// or its lane if it has one. The body is built towards the lower coordinates,
+    /// so the back segment leads when moving up or left and the front segment
+    /// leads when moving down or right. An upright padle can't move sideways and
+    /// a flat one can't move up or down.
+    pub fn can_move(&self, dir: Direction, config: &Config) -> bool {
+        let (backwards, forwards) = self.orientation.directions();
+        let along = |seg: &Segment| self.orientation.along(seg.pos);
+        let (first, last) = self.lane(config);
+        if dir == Direction::None {
+            true
+        } else if dir == backwards {
+            self.body.back().is_some_and(|seg| along(seg) > first)
+        } else if dir == forwards {
+            self.body.front().is_some_and(|seg| along(seg) < last)
+        } else {
+            false
+        }
+    }
+
+    // A helper function that determines whether
+    // the ball meats a given padle based on its current position,
+    // and if so which segment it is on. Index 0 is the front, which
+    // is the bottom end of the padle. A big ball can cover several
+    // segments, and then an end of the padle wins, so it still gets
+    // its push.
+    pub fn meats_ball(&self, ball: &Ball) -> Option<usize> {
+        (0..self.body.len())
+            .filter(|&index| ball.covers(self.body[index].pos))
+            .max_by_key(|&index| self.spin_at(index).abs())
+    }
+
+    /// The push along the padle a ball gets from hitting the segment at `index`.
+    /// The top end of an upright padle sends it upwards and the bottom end
+    /// downwards, while the left end of a flat padle sends it leftwards and the
+    /// right end rightwards. The rest of the padle leaves it as it was.
+    pub fn spin_at(&self, index: usize) -> i16 {
+        // The body goes from the end with the highest coordinate along the padle
+        // to the one with the lowest, which are the bottom and the top of an
+        // upright padle and the right and the left of a flat one, so the same
+        // push works for both.
+        if self.body.len() < 2 {
+            0
+        } else if index == self.body.len() - 1 {
+            -1
+        } else if index == 0 {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// Each padle is either played by a person at the keyboard or by the computer.
+/// For practice, player 2 can also be left out and replaced by a plain wall, and
+/// over the network player 2 is somebody at another computer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum PlayerKind {
+    Human,
+    Ai,
+    Wall,
+    Remote,
+}
+
+/// The screens the game moves through: the main menu, the match itself and the
+/// result once somebody has won. When nobody touches the menu for a while, the
+/// game plays a demo behind it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum AppState {
+    Menu,
+    Demo,
+    Playing,
+    GameOver,
+}
+
+/// The settings the players can change from the pause screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Setting {
+    Volume,
+    BallSpeed,
+    Colors,
+    Outlines,
+    Torus,
+}
+
+// The settings in the order they are listed, and how much one press of Left or
+// Right changes the ones that are numbers by.
+const SETTINGS: [Setting; 5] = [
+    Setting::Volume,
+    Setting::BallSpeed,
+    Setting::Colors,
+    Setting::Outlines,
+    Setting::Torus,
+];
+const SETTING_STEP: f32 = 0.1;
+
+impl Setting {
+    /// Moves a setting that is a number `steps` steps up or down, staying within
+    /// its range, and flips one that is either on or off. We round to whole steps
+    /// so repeated presses don't pile up rounding errors. The colors aren't part
+    /// of the config, `GameState::set_theme` changes those.
+    fn adjust(self, config: &mut Config, steps: f32) {
+        let (value, min, max) = match self {
+            Setting::Volume => (&mut config.volume, 0.0, 1.0),
+            Setting::BallSpeed => (&mut config.ball_speed, 0.5, 2.0),
+            Setting::Outlines => {
+                config.outlines = !config.outlines;
+                return;
+            }
+            Setting::Torus => {
+                config.torus = !config.torus;
+                return;
+            }
+            Setting::Colors => return,
+        };
+        *value = ((*value / SETTING_STEP + steps).round() * SETTING_STEP).clamp(min, max);
+    }
+}
+
+// The choices on the main menu, and who plays player 2 for each of them.
+const MENU_OPTIONS: [(&str, PlayerKind); 3] = [
+    ("1 Player", PlayerKind::Ai),
+    ("2 Players", PlayerKind::Human),
+    ("Practice", PlayerKind::Wall),
+];
+
+/// Now we have the heart of our game, the `GameState`. With the `gui` feature
+/// this struct implements ggez's `EventHandler` trait and will therefore drive
+/// everything else that happens in our game.
+///
+/// It can be written out as JSON to see what is going on while debugging, or to
+/// save a match and read it back later. The parts that are big, or that aren't
+/// really the state of the game, are left out.
+#[derive(Serialize, Deserialize)]
+pub struct GameState {
+    /// The settings the game was launched with
+    config: Config,
+    /// Then we need Padles, a team of them for player 1 and one for player 2
+    padles: [Vec<Padle>; 2],
+    /// Who controls player 2's padle
+    player2: PlayerKind,
+    /// The ball
+    balls: Vec<Ball>,
+    /// Where the ball has been during this match
+    #[serde(skip)]
+    heat_map: HeatMap,
+    /// Whether the heat map overlay is drawn
+    show_heat_map: bool,
+    /// Whether the paddles' grid coordinates are drawn (debug builds only)
+    show_coordinates: bool,
+    /// Whether the frame rate and how long the last frame took to update and
+    /// draw are shown, and those times
+    show_performance: bool,
+    update_time: Duration,
+    draw_time: Duration,
+    /// Which of the `THEMES` the game is drawn in
+    theme_index: usize,
+    /// The power-up lying on the court, if there is one
+    power_up: Option<PowerUp>,
+    /// The blocks on the court the ball bounces off
+    obstacles: Vec<GridPosition>,
+    /// The sparks flying about from the last few hits
+    #[serde(skip)]
+    particles: Vec<Particle>,
+    /// How many more updates the screen shakes for, and how far it is shaken
+    /// right now in pixels
+    shake_timer: u32,
+    shake_offset: (f32, f32),
+    /// How many more updates the flash for a point lasts, and which player won it
+    flash_timer: u32,
+    flash_player: u8,
+    /// The current size of the window in pixels. The board is always drawn at the
+    /// size the config gives it and then stretched to fill the window.
+    window_size: (f32, f32),
+    /// The points each player has won so far in the current game
+    score1: u32,
+    score2: u32,
+    /// The games each player has won so far in the match
+    rounds_won1: u32,
+    rounds_won2: u32,
+    /// How many times the ball has been hit by a padle since the last point, and
+    /// the most it has been in any point of the match so far
+    current_rally: u32,
+    longest_rally: u32,
+    /// How many points have been played in the match, which decides who gets the
+    /// serve when it alternates
+    points_played: u32,
+    /// How long the match has been played for, and how long it may last in a
+    /// timed match. Only the updates of a match count, not the paused ones.
+    elapsed: Duration,
+    time_limit: Option<Duration>,
+    /// For each computer player, how many more updates it waits before it looks
+    /// at the ball again, and the row it is heading for until then
+    ai_timers: [u32; 2],
+    ai_targets: [Option<i16>; 2],
+    /// How many rows a computer player may miss the row it wants by, either way.
+    /// It starts out at what the difficulty says.
+    ai_aim_error: i16,
+    /// Which screen we are on, which also tells whether the game is over
+    app_state: AppState,
+    /// The main menu option currently highlighted
+    menu_selection: usize,
+    /// How many updates the menu has been waiting for a key
+    idle_ticks: u32,
+    /// Whether the players have paused the game
+    paused: bool,
+    /// While the settings overlay is open on top of the pause screen, which of
+    /// the `SETTINGS` is highlighted
+    settings_selection: Option<usize>,
+    /// How many more updates the ball waits before it is served
+    serve_timer: u32,
+    /// When the players serve themselves, whether the balls are waiting for
+    /// `server` to send them off
+    waiting_for_serve: bool,
+    server: u8,
+    /// The records kept across sessions, and the file they are saved to
+    high_scores: HighScores,
+    high_scores_path: Option<PathBuf>,
+    /// The sounds, pictures and drawing batch of the window, if there is one
+    #[cfg(feature = "gui")]
+    #[serde(skip)]
+    resources: gui::Resources,
+    /// Which keys move which padle
+    #[serde(skip)]
+    key_bindings: KeyBindings,
+    /// The keys the players set up in a file, if they set up keys of their own,
+    /// which Tab comes back to after the ready-made layouts
+    #[serde(skip)]
+    own_key_bindings: Option<KeyBindings>,
+    /// How many steps the game has taken
+    tick: u64,
+    /// Every key pressed so far, and the file it is saved to once the match is over
+    #[serde(skip)]
+    recording: Replay,
+    replay_path: Option<PathBuf>,
+    /// When we are playing a replay, the key changes that are still to come
+    #[serde(skip)]
+    playback: Option<VecDeque<replay::Input>>,
+    /// The other instance of the game when playing over the network
+    #[serde(skip)]
+    peer: Option<Peer>,
+    /// Having joined a game, the presses and releases of our padle's keys since
+    /// the last update, still to be sent to the host
+    #[serde(skip)]
+    inputs_for_host: Vec<(Direction, bool)>,
+    /// What went wrong along the way that the players should hear about, until
+    /// the front end takes it with `take_notices`
+    #[serde(skip)]
+    notices: Vec<String>,
+    /// Our RNG state
+    #[serde(with = "savegame::rng_state")]
+    rng: Rand32,
+}
+
+impl GameState {
+    /// Sets up a game from a fixed seed. Two games created with the same seed start
+    /// out identically, which is what we want for tests and replays.
+    pub fn with_seed(seed: u64, config: Config) -> Self {
+        let grid_size = config.grid_size;
+        let mut rng = Rand32::new(seed);
+        let obstacles = place_obstacles(&mut rng, &config);
+
+        let mut state = GameState {
+            config,
+            padles: starting_padles(&config),
+            player2: PlayerKind::Human,
+            balls: Vec::new(),
+            heat_map: HeatMap::new(grid_size),
+            show_heat_map: false,
+            show_coordinates: false,
+            show_performance: false,
+            update_time: Duration::ZERO,
+            draw_time: Duration::ZERO,
+            theme_index: 0,
+            power_up: None,
+            obstacles,
+            particles: Vec::new(),
+            shake_timer: 0,
+            shake_offset: (0.0, 0.0),
+            flash_timer: 0,
+            flash_player: 1,
+            window_size: config.screen_size(),
+            score1: 0,
+            score2: 0,
+            rounds_won1: 0,
+            rounds_won2: 0,
+            current_rally: 0,
+            longest_rally: 0,
+            points_played: 0,
+            elapsed: Duration::ZERO,
+            time_limit: config.time_limit.map(|seconds| Duration::from_secs(seconds.into())),
+            ai_timers: [0; 2],
+            ai_targets: [None; 2],
+            ai_aim_error: config.difficulty.ai_aim_error(),
+            app_state: AppState::Menu,
+            menu_selection: 0,
+            idle_ticks: 0,
+            paused: false,
+            settings_selection: None,
+            serve_timer: config.ticks(SERVE_COUNTDOWN_TICKS),
+            waiting_for_serve: false,
+            server: 1,
+            high_scores: HighScores::default(),
+            high_scores_path: None,
+            #[cfg(feature = "gui")]
+            resources: gui::Resources::default(),
+            key_bindings: KeyBindings::default(),
+            own_key_bindings: None,
+            tick: 0,
+            recording: Replay::new(seed, config),
+            replay_path: None,
+            playback: None,
+            peer: None,
+            inputs_for_host: Vec::new(),
+            notices: Vec::new(),
+            rng,
+        };
+
+        // Then we choose a random place to put each ball using the helper we made
+        // earlier, and a random player to serve it to.
+        for _ in 0..config.ball_count {
+            let mut ball = Ball::sized(state.random_ball_pos(), &config);
+            ball.serve(&mut state.rng, None);
+            state.balls.push(ball);
+        }
+        state.set_theme(0);
+        state
+    }
+
+    /// A random place for a new ball where it doesn't overlap either padle, which
+    /// would count as a hit straight away. On a board so cramped that we keep
+    /// missing the free cells, the ball goes in the middle of the board instead.
+    fn random_ball_pos(&mut self) -> GridPosition {
+        let (width, height) = self.config.grid_size;
+        for _ in 0..BALL_SPAWN_ATTEMPTS {
+            let pos = GridPosition::random(&mut self.rng, width, height);
+            let ball = Ball::sized(pos, &self.config);
+            let mut padle_cells = self.padles.iter().flatten().flat_map(Padle::cells);
+            if !padle_cells.any(|cell| ball.covers(cell)) {
+                return ball.pos;
+            }
+        }
+        GridPosition::new(width / 2, height / 2)
+    }
+
+    /// Sets up a game that plays `replay` back. It starts from the recorded seed
+    /// and settings and takes its key presses from the recording rather than from
+    /// the players.
+    pub fn from_replay(replay: Replay) -> Self {
+        let mut state = GameState::with_seed(replay.seed, replay.config);
+        state.playback = Some(replay.inputs.into_iter().collect());
+        state
+    }
+
+    /// Everything that happens in one update. Over the network that involves the
+    /// other player, otherwise it's all up to us.
+    pub fn advance(&mut self) -> Option<Sound> {
+        let result = match self.peer {
+            Some(Peer::Host(_)) => self.advance_hosting(),
+            Some(Peer::Join(_)) => self.advance_joined(),
+            None => return self.advance_locally(),
+        };
+        result.unwrap_or_else(|e| {
+            self.lose_peer(e);
+            None
+        })
+    }
+
+    /// Hosting, we wait for the keys the other player pressed and let go, take our
+    /// update and then tell them what the board looks like now.
+    fn advance_hosting(&mut self) -> io::Result<Option<Sound>> {
+        let Some(peer) = &mut self.peer else {
+            return Ok(None);
+        };
+        if let Message::Input(inputs) = peer.connection().receive()? {
+            let padle = &mut self.padles[1][0];
+            for (dir, pressed) in inputs {
+                if pressed {
+                    padle.press(dir);
+                } else {
+                    padle.release(dir);
+                }
+            }
+        }
+
+        let sound = self.advance_locally();
+        let snapshot = Message::Snapshot(self.snapshot(sound));
+        if let Some(peer) = &mut self.peer {
+            peer.connection().send(&snapshot)?;
+        }
+        Ok(sound)
+    }
+
+    /// Having joined, we tell the host which of our keys went down or up and then
+    /// wait for them to show us the board after their update.
+    fn advance_joined(&mut self) -> io::Result<Option<Sound>> {
+        let inputs = std::mem::take(&mut self.inputs_for_host);
+        let Some(peer) = &mut self.peer else {
+            return Ok(None);
+        };
+        let connection = peer.connection();
+        connection.send(&Message::Input(inputs))?;
+        match connection.receive()? {
+            Message::Snapshot(snapshot) => {
+                self.check_snapshot(&snapshot)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(self.apply_snapshot(snapshot))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// When the connection breaks the game goes on without the other player. A
+    /// host hands their padle to the computer, and a player who joined goes back
+    /// to their own menu.
+    fn lose_peer(&mut self, error: io::Error) {
+        self.notices.push(format!("Lost the connection to the other player: {}", error));
+        match self.peer.take() {
+            Some(Peer::Host(_)) => {
+                self.player2 = PlayerKind::Ai;
+                self.padles[1].iter_mut().for_each(Padle::release_all);
+            }
+            Some(Peer::Join(_)) => self.start_over(),
+            None => (),
+        }
+    }
+
+    /// Captures what the player who joined needs to draw the board, along with
+    /// the `sound` this update made.
+    fn snapshot(&self, sound: Option<Sound>) -> Snapshot {
+        let padle = |padle: &Padle| (padle.center().y, padle.length());
+        Snapshot {
+            balls: self.balls.iter().map(|ball| (ball.pos, ball.velocity)).collect(),
+            padles: self.padles.each_ref().map(|team| team.iter().map(padle).collect()),
+            score: (self.score1, self.score2),
+            rounds: (self.rounds_won1, self.rounds_won2),
+            rallies: (self.current_rally, self.longest_rally),
+            elapsed: self.elapsed,
+            flash: (self.flash_timer, self.flash_player),
+            highlights: self
+                .padles
+                .each_ref()
+                .map(|team| team.iter().map(|padle| padle.highlight_timer).collect()),
+            app_state: self.app_state,
+            paused: self.paused,
+            serve_timer: self.serve_timer,
+            obstacles: self.obstacles.clone(),
+            power_up: self.power_up,
+            sound,
+        }
+    }
+
+    /// Puts the board the host sent us in place of ours, and returns the sound
+    /// that goes with it.
+    fn apply_snapshot(&mut self, snapshot: Snapshot) -> Option<Sound> {
+        // The balls we already have just move, so they keep their trails
+        if self.balls.len() == snapshot.balls.len() {
+            for (ball, (pos, velocity)) in self.balls.iter_mut().zip(snapshot.balls) {
+                let left = ball.pos;
+                ball.pos = pos;
+                ball.velocity = velocity;
+                ball.extend_trail(left);
+                self.heat_map.record(pos);
+            }
+        } else {
+            self.balls = snapshot
+                .balls
+                .into_iter()
+                .map(|(pos, velocity)| {
+                    let mut ball = Ball::sized(pos, &self.config);
+                    ball.velocity = velocity;
+                    ball
+                })
+                .collect();
+        }
+
+        let config = &self.config;
+        let teams = snapshot.padles.into_iter().zip(snapshot.highlights);
+        for (team, (padles, highlights)) in self.padles.iter_mut().zip(teams) {
+            let padles = padles.into_iter().zip(highlights);
+            for (padle, ((center, length), highlight)) in team.iter_mut().zip(padles) {
+                padle.rebuild(center, length, config);
+                padle.highlight_timer = highlight;
+            }
+        }
+        (self.score1, self.score2) = snapshot.score;
+        (self.rounds_won1, self.rounds_won2) = snapshot.rounds;
+        (self.current_rally, self.longest_rally) = snapshot.rallies;
+        self.elapsed = snapshot.elapsed;
+        (self.flash_timer, self.flash_player) = snapshot.flash;
+        self.app_state = snapshot.app_state;
+        self.paused = snapshot.paused;
+        self.serve_timer = snapshot.serve_timer;
+        self.obstacles = snapshot.obstacles;
+        self.power_up = snapshot.power_up;
+        snapshot.sound
+    }
+
+    /// Everything that happens in one update of a game that isn't shared: when
+    /// playing a replay the recorded keys for this tick come in first, then if a
+    /// match or the demo is going on and not paused the game takes a step.
+    fn advance_locally(&mut self) -> Option<Sound> {
+        while let Some(input) = self.next_replayed_input() {
+            if input.pressed {
+                self.key_down(input.key);
+            } else {
+                self.key_up(input.key);
+            }
+        }
+
+        // A menu that nobody uses starts the demo. Replays don't have one.
+        if self.app_state == AppState::Menu && self.playback.is_none() {
+            self.idle_ticks += 1;
+            if self.idle_ticks >= self.config.ticks(DEMO_IDLE_TICKS) {
+                self.start_demo();
+            }
+        }
+
+        match self.app_state {
+            AppState::Playing if !self.paused => self.step(),
+            AppState::Demo => self.step(),
+            _ => None,
+        }
+    }
+
+    /// Sets up a fresh board where the computer plays both padles.
+    fn start_demo(&mut self) {
+        self.start_over();
+        self.app_state = AppState::Demo;
+        self.player2 = PlayerKind::Ai;
+        self.serve_timer = 0;
+    }
+
+    /// Throws away the current board and starts again from the menu with a new
+    /// seed, which also starts a new recording. Everything that belongs to the
+    /// players rather than to a match, like the high scores, the key bindings and
+    /// the overlays they switched on, is kept.
+    fn start_over(&mut self) {
+        let seed = ((self.rng.rand_u32() as u64) << 32) | self.rng.rand_u32() as u64;
+        let theme_index = self.theme_index;
+        *self = GameState {
+            show_heat_map: self.show_heat_map,
+            show_coordinates: self.show_coordinates,
+            show_performance: self.show_performance,
+            window_size: self.window_size,
+            high_scores: self.high_scores,
+            high_scores_path: self.high_scores_path.take(),
+            #[cfg(feature = "gui")]
+            resources: std::mem::take(&mut self.resources),
+            key_bindings: std::mem::take(&mut self.key_bindings),
+            own_key_bindings: self.own_key_bindings.take(),
+            replay_path: self.replay_path.take(),
+            peer: self.peer.take(),
+            notices: std::mem::take(&mut self.notices),
+            ..GameState::with_seed(seed, self.config)
+        };
+        self.set_theme(theme_index);
+    }
+
+    /// Saves the match to `path`, so it can be picked up again with `load`.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        savegame::save_game(path, self)
+    }
+
+    /// Reads a match saved with `save` back from `path`. The sounds, key bindings
+    /// and the like aren't part of the save and are left empty. The heat map
+    /// starts over, and a resumed match doesn't start from a seed, so it can't be
+    /// saved as a replay.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut state = savegame::load_game(path)?;
+        state.heat_map = HeatMap::new(state.config.grid_size);
+        state.recording = Replay::new(0, state.config);
+        state.replay_path = None;
+        Ok(state)
+    }
+
+    /// Checks that a match read back from a file is one we can play on with.
+    /// Its settings must be ones the command line would have taken, each team
+    /// must have as many padles as the settings say, and every ball, padle,
+    /// obstacle and power-up must be on the board. The theme, the selections on
+    /// the menus and the players it names must all be ones that exist.
+    fn validate(&self) -> Result<(), String> {
+        self.config.validate()?;
+        let on_board = |pos| self.on_board(pos);
+
+        for team in &self.padles {
+            if team.len() != self.config.team_size as usize {
+                let expected = self.config.team_size;
+                return Err(format!("a team has {} padles instead of {}", team.len(), expected));
+            }
+            for padle in team {
+                if padle.body.is_empty() || !padle.cells().all(on_board) {
+                    return Err("a padle is not on the board".to_string());
+                }
+            }
+        }
+
+        for ball in &self.balls {
+            if ball.size != self.config.ball_size || !self.ball_on_board(ball.pos) {
+                return Err(format!("a ball at {}, {} is not on the board", ball.pos.x, ball.pos.y));
+            }
+        }
+
+        let power_up = self.power_up.as_ref().map(|power_up| power_up.pos);
+        if !self.obstacles.iter().copied().chain(power_up).all(on_board) {
+            return Err("an obstacle or power-up is not on the board".to_string());
+        }
+
+        if self.theme_index >= THEMES.len() {
+            return Err(format!("there is no theme {}", self.theme_index));
+        }
+        if self.menu_selection >= MENU_OPTIONS.len()
+            || self.settings_selection.is_some_and(|selection| selection >= SETTINGS.len())
+        {
+            return Err("a selection is off the menu".to_string());
+        }
+        if !(1..=2).contains(&self.flash_player) || !(1..=2).contains(&self.server) {
+            return Err("a player is neither player 1 nor player 2".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Checks a board the host sent us by the same rules `validate` uses for a
+    /// saved match: each team has as many padles as the settings say, every padle
+    /// is at least one cell long, and every ball, obstacle and power-up is on the
+    /// board. Padles that are too long or off the board are brought back onto it
+    /// when they are rebuilt, so those are fine.
+    fn check_snapshot(&self, snapshot: &Snapshot) -> Result<(), String> {
+        let team_size = self.config.team_size as usize;
+        if snapshot.padles.iter().any(|team| team.len() != team_size)
+            || snapshot.highlights.iter().any(|team| team.len() != team_size)
+        {
+            return Err(format!("a team doesn't have {} padles", team_size));
+        }
+        if snapshot.padles.iter().flatten().any(|&(_, length)| length < 1) {
+            return Err("a padle is less than one cell long".to_string());
+        }
+
+        for &(pos, _) in &snapshot.balls {
+            if !self.ball_on_board(pos) {
+                return Err(format!("a ball at {}, {} is not on the board", pos.x, pos.y));
+            }
+        }
+
+        let power_up = snapshot.power_up.as_ref().map(|power_up| power_up.pos);
+        if !snapshot.obstacles.iter().copied().chain(power_up).all(|pos| self.on_board(pos)) {
+            return Err("an obstacle or power-up is not on the board".to_string());
+        }
+        if !(1..=2).contains(&snapshot.flash.1) {
+            return Err("a player is neither player 1 nor player 2".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Tells whether `pos` is a cell of the board.
+    fn on_board(&self, pos: GridPosition) -> bool {
+        let (width, height) = self.config.grid_size;
+        (0..width).contains(&pos.x) && (0..height).contains(&pos.y)
+    }
+
+    /// Tells whether a ball of the size the settings give with its top left
+    /// corner at `pos` lies on the board all the way to its far corner.
+    fn ball_on_board(&self, pos: GridPosition) -> bool {
+        let reach = self.config.ball_size - 1;
+        self.on_board(pos) && self.on_board(GridPosition::new(pos.x + reach, pos.y + reach))
+    }
+
+    /// Saves the match to the usual place, which F5 does.
+    fn save_match(&mut self) {
+        let Some(path) = savegame::default_path() else {
+            return;
+        };
+        if let Err(e) = self.save(&path) {
+            let notice = format!("Could not save the match to {}: {}", path.display(), e);
+            self.notices.push(notice);
+        }
+    }
+
+    /// Picks up the match saved in the usual place, which F9 does. Just like
+    /// `start_over`, everything that belongs to the players rather than to a
+    /// match is kept. A match that was going on comes back paused, so nobody is
+    /// caught off guard.
+    fn resume_saved_match(&mut self) {
+        let Some(path) = savegame::default_path() else {
+            return;
+        };
+        let saved = match GameState::load(&path) {
+            Ok(saved) => saved,
+            Err(e) => {
+                let path = path.display();
+                let notice = format!("Could not load the saved match from {}: {}", path, e);
+                self.notices.push(notice);
+                return;
+            }
+        };
+        *self = GameState {
+            show_performance: self.show_performance,
+            window_size: self.window_size,
+            high_scores: self.high_scores,
+            high_scores_path: self.high_scores_path.take(),
+            #[cfg(feature = "gui")]
+            resources: std::mem::take(&mut self.resources),
+            key_bindings: std::mem::take(&mut self.key_bindings),
+            own_key_bindings: self.own_key_bindings.take(),
+            notices: std::mem::take(&mut self.notices),
+            ..saved
+        };
+        if self.app_state == AppState::Playing {
+            self.paused = true;
+        }
+    }
+
+    /// Starts a new match with the same players straight away. Scores, padles,
+    /// balls and obstacles all start over, and the new board gets its seed from
+    /// our RNG rather than starting again from a fixed one.
+    pub fn reset(&mut self) {
+        let menu_selection = self.menu_selection;
+        self.start_over();
+        self.menu_selection = menu_selection;
+        self.start_match();
+    }
+
+    /// Switches to the theme at `index` in `THEMES`, which also repaints the
+    /// padles.
+    fn set_theme(&mut self, index: usize) {
+        self.theme_index = index;
+        let theme = THEMES[index];
+        for (team, color) in self.padles.iter_mut().zip(theme.players) {
+            for padle in team {
+                padle.set_colors(color, theme.padle_top);
+            }
+        }
+    }
+
+    /// Takes the next recorded key change off the replay if it is due by now.
+    fn next_replayed_input(&mut self) -> Option<replay::Input> {
+        let inputs = self.playback.as_mut()?;
+        if inputs.front()?.tick > self.tick {
+            return None;
+        }
+        inputs.pop_front()
+    }
+
+    /// Advances the game by one tick. This is all of the game logic and needs no
+    /// `Context`, so it can run without a window, for example in tests. It returns
+    /// the sound that goes with what happened, if any.
+    pub fn step(&mut self) -> Option<Sound> {
+        self.tick += 1;
+        self.update_shake();
+        self.update_particles();
+        self.flash_timer = self.flash_timer.saturating_sub(1);
+
+        // If the computer plays player 2, it decides on its move first. In the
+        // demo it plays player 1 as well.
+        if self.app_state == AppState::Demo {
+            self.update_ai(1);
+        }
+        if self.player2 == PlayerKind::Ai {
+            self.update_ai(2);
+        }
+
+        // Then we tell the padles and ball to update itself. During the
+        // serve countdown the padles can get ready but the ball waits.
+        let teams = if self.player2 == PlayerKind::Wall { 1 } else { 2 };
+        for padle in self.padles[..teams].iter_mut().flatten() {
+            padle.update(&self.config);
+        }
+        if self.serve_timer > 0 {
+            self.serve_timer -= 1;
+            return None;
+        }
+
+        // A serve by hand waits for the server's key. The computer, the practice
+        // wall and a player across the network don't press keys here, so they
+        // serve straight away, up or down at random.
+        if self.waiting_for_serve {
+            if !self.serves_by_hand() {
+                let dir = if self.rng.rand_range(0..2) == 0 {
+                    Direction::Up
+                } else {
+                    Direction::Down
+                };
+                self.launch_serve(dir);
+            }
+            return None;
+        }
+
+        // In a timed match the clock runs on every update the ball is in play,
+        // so the countdown and the wait for a serve don't use up the match. Once
+        // it has run out whoever is ahead wins.
+        if self.app_state == AppState::Playing && self.player2 != PlayerKind::Wall {
+            self.elapsed += Duration::from_secs(1) / self.config.tick_rate;
+            if self.time_limit.is_some() {
+                if let Some(winner) = self.match_winner() {
+                    self.finish_match(winner);
+                    return None;
+                }
+            }
+        }
+
+        // Every now and then a power-up turns up on the court.
+        let chance = self.config.ticks(POWER_UP_CHANCE);
+        if self.power_up.is_none() && self.rng.rand_range(0..chance) == 0 {
+            self.power_up = Some(PowerUp::random(&mut self.rng, &self.config));
+        }
+
+        // In continuous mode the balls move once per frame instead, see
+        // `advance_frame`.
+        if self.config.continuous {
+            return None;
+        }
+        self.update_balls(self.config.tick_scale())
+    }
+
+    /// In continuous mode, moves the balls along by the `delta` the last frame
+    /// took, as long as a match or the demo is going on and the ball is in play.
+    /// Everything else still happens in the fixed updates of `advance`.
+    pub fn advance_frame(&mut self, delta: Duration) -> Option<Sound> {
+        let running = match self.app_state {
+            AppState::Playing => !self.paused,
+            AppState::Demo => true,
+            _ => false,
+        };
+        if !self.config.continuous || !running || self.serve_timer > 0 || self.waiting_for_serve {
+            return None;
+        }
+        let slowed = self.update_rate() as f32 / self.config.tick_rate as f32;
+        self.update_balls(delta.as_secs_f32() * DESIRED_FPS as f32 * slowed)
+    }
+
+    /// Moves every ball along by `elapsed`, counted in updates at `DESIRED_FPS`,
+    /// and deals with whatever happened to them. It returns the sound that goes
+    /// with the most important thing that happened, if anything did.
+    fn update_balls(&mut self, elapsed: f32) -> Option<Sound> {
+        // Every ball moves on its own and bounces off the padles by itself, and
+        // tells us what happened to it.
+        let mut events = Vec::with_capacity(self.balls.len());
+        for ball in &mut self.balls {
+            let padles2: &[Padle] = if self.player2 == PlayerKind::Wall {
+                &[]
+            } else {
+                &self.padles[1]
+            };
+            let event = ball.update(
+                [&self.padles[0], padles2],
+                &self.obstacles,
+                &mut self.heat_map,
+                &mut self.power_up,
+                elapsed,
+                &self.config,
+            );
+
+            // A ball carrying a power-up hands it to the padle that hits it, and
+            // every padle hit shakes the screen.
+            if let BallEvent::HitPaddle(player, member) = event {
+                self.current_rally += 1;
+                let padle = &mut self.padles[usize::from(player - 1)][member];
+                padle.highlight(&self.config);
+                if let Some(kind) = ball.power_up.take() {
+                    padle.apply_power_up(kind, &self.config);
+                }
+                self.shake_timer = self.config.ticks(SHAKE_TICKS);
+
+                // Sparks fly off the middle of the ball, the way it is now going
+                let at = ball.center_on_screen(&self.config);
+                let burst = particle_burst(&mut self.rng, at, ball.velocity.dx);
+                self.particles.extend(burst);
+                let excess = self.particles.len().saturating_sub(MAX_PARTICLES);
+                self.particles.drain(..excess);
+            }
+            events.push(event);
+        }
+
+        // Next we score the balls that got past a padle, and play the sound of
+        // whatever mattered most.
+        self.check_score(&events);
+        events.into_iter().max().and_then(BallEvent::sound)
+    }
+
+    /// Moves every spark along and lets go of the ones that burnt out.
+    fn update_particles(&mut self) {
+        let elapsed = self.config.tick_scale();
+        for particle in &mut self.particles {
+            particle.update(elapsed);
+        }
+        self.particles.retain(|particle| particle.life > 0.0);
+    }
+
+    /// Picks a new random offset for the screen while it is shaking, a little
+    /// smaller every update until it is still again. It is worked out here rather
+    /// than while drawing so the RNG is used the same way when a match is replayed.
+    fn update_shake(&mut self) {
+        if self.shake_timer == 0 {
+            self.shake_offset = (0.0, 0.0);
+            return;
+        }
+
+        let strength =
+            SHAKE_STRENGTH * self.shake_timer as f32 / self.config.ticks(SHAKE_TICKS) as f32;
+        self.shake_offset = (
+            (self.rng.rand_float() * 2.0 - 1.0) * strength,
+            (self.rng.rand_float() * 2.0 - 1.0) * strength,
+        );
+        self.shake_timer -= 1;
+    }
+
+    /// Every ball that got past a padle wins a point for the other player, and then
+    /// all balls are served again at the starting speed.
+    fn check_score(&mut self, events: &[BallEvent]) {
+        let mut receiver = None;
+        for event in events {
+            if let BallEvent::ScoredOn(player) = *event {
+                if player == 1 {
+                    self.score2 += 1;
+                } else {
+                    self.score1 += 1;
+                }
+                receiver = Some(player);
+                self.flash_player = 3 - player;
+                self.flash_timer = self.config.ticks(FLASH_TICKS);
+            }
+        }
+        let Some(scored_on) = receiver else {
+            return;
+        };
+
+        // The rally is over, and it may have been the longest one yet.
+        self.longest_rally = self.longest_rally.max(self.current_rally);
+        self.current_rally = 0;
+
+        // The balls get served again, to whoever the serve rule picks.
+        self.points_played += 1;
+        let receiver = self.next_receiver(scored_on);
+        self.reset_ball(Some(receiver));
+
+        // Practicing against the wall there is nobody to win points, only the
+        // rally counts.
+        if self.player2 == PlayerKind::Wall {
+            self.score1 = 0;
+            self.score2 = 0;
+            return;
+        }
+
+        // The demo never ends, it just starts the score over whenever somebody
+        // would have won.
+        if self.app_state == AppState::Demo {
+            if self.winner().is_some() {
+                self.score1 = 0;
+                self.score2 = 0;
+            }
+            return;
+        }
+
+        // A timed match has no games or rounds, the points just add up until time
+        // runs out. If it ran out on a tie, this point decided it.
+        if self.time_limit.is_some() {
+            if let Some(winner) = self.match_winner() {
+                self.finish_match(winner);
+            }
+            return;
+        }
+
+        // Whoever wins a game wins a round. Until somebody has won the match we
+        // start the next game from zero, otherwise the match is over and we keep
+        // the last game's score on the board.
+        if let Some(winner) = self.winner() {
+            match winner {
+                1 => self.rounds_won1 += 1,
+                _ => self.rounds_won2 += 1,
+            }
+            if self.match_winner().is_none() {
+                self.score1 = 0;
+                self.score2 = 0;
+                return;
+            }
+            self.finish_match(winner);
+        }
+    }
+
+    /// Ends the match won by `winner`, and saves the high scores and the replay
+    /// if we keep them.
+    fn finish_match(&mut self, winner: u8) {
+        self.app_state = AppState::GameOver;
+        self.high_scores.record_game(self.score1, self.score2, winner);
+        if let Some(path) = &self.high_scores_path {
+            if let Err(e) = highscores::save_high_scores(path, &self.high_scores) {
+                let notice = format!("Could not save high scores to {}: {}", path.display(), e);
+                self.notices.push(notice);
+            }
+        }
+        if let Some(path) = &self.replay_path {
+            if let Err(e) = replay::save_replay(path, &self.recording) {
+                let notice = format!("Could not save the replay to {}: {}", path.display(), e);
+                self.notices.push(notice);
+            }
+        }
+    }
+
+    /// Who the next serve goes to after `scored_on` lost a point. By default that
+    /// is always the player who lost it. When the serve alternates it switches
+    /// sides every two points, whoever won them: the match's second point is
+    /// served to player 2, the next two to player 1, the two after that to
+    /// player 2 again and so on. The first serve of a match is a random one.
+    fn next_receiver(&self, scored_on: u8) -> u8 {
+        match self.config.serve_rule {
+            ServeRule::Loser => scored_on,
+            ServeRule::Alternate if (self.points_played / 2).is_multiple_of(2) => 2,
+            ServeRule::Alternate => 1,
+        }
+    }
+
+    /// The computer player simply steers its padle's center towards the ball's row.
+    /// To stay beatable it only reacts while a ball is heading its way, looks only
+    /// every so often and aims a little off, and with several balls it goes after
+    /// the closest one. It can play either `player`.
+    fn update_ai(&mut self, player: u8) {
+        // The computer only looks at the ball every so often, the less often the
+        // easier the game is, and in between it keeps going for the row it picked.
+        let index = usize::from(player - 1);
+        if self.ai_timers[index] > 0 {
+            self.ai_timers[index] -= 1;
+        } else {
+            let reaction = self.config.difficulty.ai_reaction_ticks();
+            self.ai_timers[index] = self.config.ticks(reaction) - 1;
+
+            // Nobody aims perfectly, so every new target is off by a little
+            let error = self.ai_aim_error;
+            let miss = if error > 0 {
+                self.rng.rand_range(0..(2 * error + 1) as u32) as i16 - error
+            } else {
+                0
+            };
+            self.ai_targets[index] = self.ai_target(player).map(|row| row + miss);
+        }
+
+        // Every padle of the team heads for the row, or the column for a flat
+        // padle, as far as its lane lets it
+        for padle in &mut self.padles[index] {
+            let (backwards, forwards) = padle.orientation.directions();
+            let along = padle.orientation.along(padle.center());
+            padle.dir = match self.ai_targets[index] {
+                Some(target) if target < along => backwards,
+                Some(target) if target > along => forwards,
+                _ => Direction::None,
+            };
+        }
+    }
+
+    /// The row the computer playing `player` wants its padle on, going by the
+    /// closest ball heading its way. On hard it works out where that ball will
+    /// reach the padle, otherwise it just follows the ball's row. With no ball
+    /// coming it stays where it is. Flat padles want a column instead, and just
+    /// follow the ball closest to them from side to side.
+    fn ai_target(&self, player: u8) -> Option<i16> {
+        let first = &self.padles[usize::from(player - 1)][0];
+        if first.orientation == Orientation::Horizontal {
+            let row = first.center().y;
+            let ball = self.balls.iter().min_by_key(|ball| (ball.pos.y - row).abs())?;
+            return Some(ball.pos.x);
+        }
+
+        // Player 1 defends the left edge and player 2 the right one.
+        let heading = if player == 1 { -1 } else { 1 };
+        let column = first.center().x;
+        let ball = self
+            .balls
+            .iter()
+            .filter(|ball| ball.velocity.dx.signum() == heading)
+            .max_by_key(|ball| ball.pos.x * heading)?;
+
+        if self.config.difficulty.ai_predicts() {
+            Some(landing_row(ball.pos, ball.velocity, column, &self.config))
+        } else {
+            Some(ball.pos.y)
+        }
+    }
+
+    /// Handles a key press on the main menu: either player's up and down keys move
+    /// the selection, Left and Right change the difficulty, Tab switches to the
+    /// next control layout and Enter starts a match with the
+    /// chosen players.
+    fn menu_key_down(&mut self, key: Key) {
+        if matches!(key, Key::Return | Key::NumpadEnter) {
+            self.start_match();
+            return;
+        }
+        // Tab goes through the control layouts. Keys set up in a file come
+        // first, then the ready-made layouts, and after the last of those the
+        // keys from the file again.
+        if key == Key::Tab {
+            self.key_bindings = match self.key_bindings.preset_index() {
+                Some(index) if index + 1 < keybindings::PRESETS.len() => {
+                    KeyBindings::preset(index + 1)
+                }
+                Some(_) => self.own_key_bindings.clone().unwrap_or_else(|| KeyBindings::preset(0)),
+                None => KeyBindings::preset(0),
+            };
+            return;
+        }
+        // Left and Right pick the difficulty the match is played at
+        if matches!(key, Key::Left | Key::Right) {
+            let index = DIFFICULTIES
+                .iter()
+                .position(|&difficulty| difficulty == self.config.difficulty)
+                .unwrap_or(0);
+            let step = if key == Key::Left { DIFFICULTIES.len() - 1 } else { 1 };
+            self.set_difficulty(DIFFICULTIES[(index + step) % DIFFICULTIES.len()]);
+            return;
+        }
+        match self.key_bindings.get(key) {
+            Some((_, Direction::Up)) => {
+                self.menu_selection =
+                    (self.menu_selection + MENU_OPTIONS.len() - 1) % MENU_OPTIONS.len();
+            }
+            Some((_, Direction::Down)) => {
+                self.menu_selection = (self.menu_selection + 1) % MENU_OPTIONS.len();
+            }
+            _ => (),
+        }
+    }
+
+    /// Handles a key press while the settings overlay is open: Up and Down pick a
+    /// setting, Left and Right change it and Escape goes back to the pause screen.
+    /// Changes go straight into the config, so they count from the next update on.
+    fn settings_key_down(&mut self, key: Key) {
+        let Some(selection) = self.settings_selection else {
+            return;
+        };
+        let setting = SETTINGS[selection];
+        match key {
+            Key::Escape => self.settings_selection = None,
+            Key::Up => {
+                self.settings_selection = Some((selection + SETTINGS.len() - 1) % SETTINGS.len());
+            }
+            Key::Down => self.settings_selection = Some((selection + 1) % SETTINGS.len()),
+            Key::Left | Key::Right if setting == Setting::Colors => {
+                let step = if key == Key::Left { THEMES.len() - 1 } else { 1 };
+                self.set_theme((self.theme_index + step) % THEMES.len());
+            }
+            Key::Left => setting.adjust(&mut self.config, -1.0),
+            Key::Right => setting.adjust(&mut self.config, 1.0),
+            _ => (),
+        }
+    }
+
+    /// Switches to `difficulty` before a match starts, which gives both players
+    /// new padles of the length that goes with it.
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.config.difficulty = difficulty;
+        self.ai_aim_error = difficulty.ai_aim_error();
+        self.padles = starting_padles(&self.config);
+        self.set_theme(self.theme_index);
+    }
+
+    /// Leaves the menu and starts playing, beginning with the serve countdown.
+    fn start_match(&mut self) {
+        self.player2 = if matches!(self.peer, Some(Peer::Host(_))) {
+            PlayerKind::Remote
+        } else {
+            MENU_OPTIONS[self.menu_selection].1
+        };
+        self.padles.iter_mut().flatten().for_each(Padle::release_all);
+        self.begin_serve();
+        self.app_state = AppState::Playing;
+    }
+
+    /// Gets the balls ready to be served. Usually they go by themselves once the
+    /// countdown is over, but when the players serve by hand they wait for the
+    /// player they are heading away from.
+    fn begin_serve(&mut self) {
+        if !self.config.manual_serve {
+            self.serve_timer = self.config.ticks(SERVE_COUNTDOWN_TICKS);
+            return;
+        }
+        self.serve_timer = 0;
+        self.waiting_for_serve = true;
+        let towards_player1 = self.balls.first().is_some_and(|ball| ball.velocity.dx < 0);
+        self.server = if towards_player1 { 2 } else { 1 };
+    }
+
+    /// Tells whether the serve is up to a person at this keyboard, rather than
+    /// to the computer, the wall or somebody across the network.
+    fn serves_by_hand(&self) -> bool {
+        self.app_state == AppState::Playing
+            && (self.server == 1 || self.player2 == PlayerKind::Human)
+    }
+
+    /// Sends the waiting balls off towards the other player, going up or down
+    /// as `dir` says.
+    fn launch_serve(&mut self, dir: Direction) {
+        let dy = if dir == Direction::Up { -1 } else { 1 };
+        for ball in &mut self.balls {
+            ball.velocity.dy = dy;
+        }
+        self.waiting_for_serve = false;
+    }
+
+    /// Handles a key going down, whether a player pressed it or a replay did.
+    /// Every key is recorded first so the match can be replayed later.
+    pub fn key_down(&mut self, key: Key) {
+        self.recording.record(self.tick, key, true);
+
+        // F3 shows or hides the performance overlay, wherever we are
+        if key == Key::F3 {
+            self.show_performance = !self.show_performance;
+            return;
+        }
+
+        // Having joined somebody else's game, the keys only steer our padle
+        if matches!(self.peer, Some(Peer::Join(_))) {
+            if let Some((_, dir)) = self.key_bindings.get(key) {
+                self.steer_joined(dir, true);
+            }
+            return;
+        }
+
+        // F9 picks up the saved match from wherever we are, unless this game is
+        // a replay or played over the network
+        if key == Key::F9 && self.playback.is_none() && self.peer.is_none() {
+            self.resume_saved_match();
+            return;
+        }
+
+        // Any key ends the demo and goes back to the menu
+        if self.app_state == AppState::Demo {
+            self.start_over();
+            return;
+        }
+
+        // On the menu the keys pick what to play and nothing else
+        if self.app_state == AppState::Menu {
+            self.idle_ticks = 0;
+            self.menu_key_down(key);
+            return;
+        }
+
+        // The settings overlay takes every key while it is open
+        if self.settings_selection.is_some() {
+            self.settings_key_down(key);
+            return;
+        }
+
+        // F5 saves the match so it can be picked up again later
+        if key == Key::F5 {
+            if self.playback.is_none() && self.peer.is_none() {
+                self.save_match();
+            }
+            return;
+        }
+
+        // O opens the settings while paused
+        if key == Key::O && self.paused {
+            self.settings_selection = Some(0);
+            return;
+        }
+
+        // After the match R or Enter starts the next one with the same players
+        if self.app_state == AppState::GameOver
+            && matches!(key, Key::R | Key::Return | Key::NumpadEnter)
+        {
+            self.reset();
+            return;
+        }
+
+        // H toggles the heat map overlay
+        if key == Key::H {
+            self.show_heat_map = !self.show_heat_map;
+            return;
+        }
+
+        // T switches to the next color theme
+        if key == Key::T {
+            self.set_theme((self.theme_index + 1) % THEMES.len());
+            return;
+        }
+
+        // N toggles the catch net in the middle of the court
+        if key == Key::N {
+            for ball in &mut self.balls {
+                ball.catches_at_net = !ball.catches_at_net;
+            }
+            return;
+        }
+
+        // C toggles the paddle coordinate readout in debug builds
+        if DEBUG_TOOLS && key == Key::C {
+            self.show_coordinates = !self.show_coordinates;
+            return;
+        }
+
+        // F12 hands the whole game state as JSON to the front end in debug builds
+        if DEBUG_TOOLS && key == Key::F12 {
+            let notice = match serde_json::to_string_pretty(self) {
+                Ok(json) => json,
+                Err(e) => format!("Could not write out the game state: {}", e),
+            };
+            self.notices.push(notice);
+            return;
+        }
+
+        // I hands player 2 over to the computer, or back to a person
+        if key == Key::I {
+            self.player2 = match self.player2 {
+                PlayerKind::Human => PlayerKind::Ai,
+                PlayerKind::Ai => PlayerKind::Human,
+                PlayerKind::Wall => PlayerKind::Wall,
+                PlayerKind::Remote => PlayerKind::Remote,
+            };
+            self.padles[1].iter_mut().for_each(Padle::release_all);
+            return;
+        }
+
+        // P or Escape pause and resume the game
+        if matches!(key, Key::P | Key::Escape) {
+            if self.app_state == AppState::Playing {
+                self.paused = !self.paused;
+            }
+            return;
+        }
+
+        // While paused or after the game the padles don't listen, so nobody can
+        // queue up a move
+        if self.app_state != AppState::Playing || self.paused {
+            return;
+        }
+
+        // When it is their turn to serve by hand, the server's up or down key (or
+        // their teammate's) sends the balls off that way
+        if let (true, Some((player, dir))) = (self.waiting_for_serve, self.key_bindings.get(key)) {
+            if (player - 1) % 2 + 1 == self.server && self.serves_by_hand() {
+                self.launch_serve(dir);
+                return;
+            }
+        }
+
+        // Here we look the Keycode up in the key bindings, and hold the Direction
+        // it stands for down on the right padle.
+        if let Some((padle, dir)) = self.keyboard_padle(key) {
+            padle.press(dir);
+        }
+    }
+
+    /// Handles a key going up. We listen even while paused so no key is left
+    /// stuck down.
+    pub fn key_up(&mut self, key: Key) {
+        self.recording.record(self.tick, key, false);
+        if matches!(self.peer, Some(Peer::Join(_))) {
+            if let Some((_, dir)) = self.key_bindings.get(key) {
+                self.steer_joined(dir, false);
+            }
+        } else if let Some((padle, dir)) = self.keyboard_padle(key) {
+            padle.release(dir);
+        }
+    }
+
+    /// Finds the padle a key belongs to and the direction it moves that padle in.
+    /// A padle played by the computer doesn't take keyboard input.
+    fn keyboard_padle(&mut self, key: Key) -> Option<(&mut Padle, Direction)> {
+        let (player, dir) = self.key_bindings.get(key)?;
+        self.player_padle(player).map(|padle| (padle, dir))
+    }
+
+    /// Having joined a game over the network we play player 2's first padle,
+    /// with anybody's keys or the first gamepad. The host moves it for us, so a
+    /// key for `dir` going down or up is kept to be sent with the next update.
+    fn steer_joined(&mut self, dir: Direction, pressed: bool) {
+        self.inputs_for_host.push((dir, pressed));
+    }
+
+    /// The padle `player` steers. Players 1 and 2 have the first padle of their
+    /// teams, and players 3 and 4 are their teammates with the second ones, when
+    /// the teams have that many. Only people steer padles this way, so nobody gets
+    /// one on a team the computer plays.
+    fn player_padle(&mut self, player: u8) -> Option<&mut Padle> {
+        let index = usize::from(player.checked_sub(1)?);
+        let (team, member) = (index % 2, index / 2);
+        if team == 1 && self.player2 != PlayerKind::Human {
+            return None;
+        }
+        self.padles[team].get_mut(member)
+    }
+
+    /// Lets the mouse or a finger drag a padle: anywhere in the left third of the
+    /// window moves player 1's padle to that row and the right third moves player
+    /// 2's, picking whichever padle of the team has the row in its lane. The
+    /// middle third is left alone so the pointer can rest there. Nobody steers a
+    /// replay, though, and a player who joined over the network steers with keys
+    /// or a gamepad.
+    pub fn pointer_moved(&mut self, x: f32, y: f32) {
+        if self.app_state != AppState::Playing
+            || self.paused
+            || self.playback.is_some()
+            || matches!(self.peer, Some(Peer::Join(_)))
+        {
+            return;
+        }
+
+        // The window may have been resized, so we first scale the pointer back to
+        // the board's own pixels.
+        let screen_size = self.config.screen_size();
+        let x = x * screen_size.0 / self.window_size.0;
+        let y = y * screen_size.1 / self.window_size.1;
+        let row = (y / self.config.cell_size.1 as f32).floor() as i16;
+        let team = if x < screen_size.0 / 3.0 {
+            0
+        } else if x > screen_size.0 * 2.0 / 3.0 && self.player2 == PlayerKind::Human {
+            1
+        } else {
+            return;
+        };
+        let config = &self.config;
+        let mut team = self.padles[team].iter_mut();
+        if let Some(padle) = team.find(|padle| padle.lane_contains(row, config)) {
+            padle.set_center_y(row, config);
+        }
+    }
+
+    /// Takes the messages about what went wrong since the last call, like a save
+    /// that couldn't be written, for the front end to show however it likes.
+    pub fn take_notices(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notices)
+    }
+
+    /// The points each player has won so far in the current game.
+    pub fn score(&self) -> (u32, u32) {
+        (self.score1, self.score2)
+    }
+
+    /// The balls in play.
+    pub fn balls(&self) -> &[Ball] {
+        &self.balls
+    }
+
+    /// The padles of `player`'s team.
+    pub fn padles(&self, player: u8) -> &[Padle] {
+        &self.padles[usize::from(player - 1)]
+    }
+
+    /// Tells which player, if any, has reached the winning score.
+    pub fn winner(&self) -> Option<u8> {
+        if self.score1 >= WINNING_SCORE {
+            Some(1)
+        } else if self.score2 >= WINNING_SCORE {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    /// Tells which player, if any, has won enough rounds to take the match. In a
+    /// timed match it is whoever is ahead once time is up. If they are tied then,
+    /// the match goes on in sudden death until one of them is ahead.
+    pub fn match_winner(&self) -> Option<u8> {
+        if self.time_limit.is_some() {
+            if !self.time_is_up() || self.score1 == self.score2 {
+                return None;
+            }
+            return Some(if self.score1 > self.score2 { 1 } else { 2 });
+        }
+
+        if self.rounds_won1 >= ROUNDS_TO_WIN {
+            Some(1)
+        } else if self.rounds_won2 >= ROUNDS_TO_WIN {
+            Some(2)
+        } else {
+            None
+        }
+    }
+
+    /// How many times per second the game updates right now. That is the tick
+    /// rate, unless slow motion is on and the ball is in play on a point that
+    /// could end the match.
+    pub fn update_rate(&self) -> u32 {
+        if self.config.slow_motion && self.serve_timer == 0 && self.at_match_point() {
+            ((self.config.tick_rate as f32 * SLOW_MOTION_FACTOR).round() as u32).max(1)
+        } else {
+            self.config.tick_rate
+        }
+    }
+
+    /// Tells whether the point being played could win somebody the match: in a
+    /// timed match that is any point in sudden death, otherwise one that wins
+    /// the deciding game.
+    fn at_match_point(&self) -> bool {
+        if self.app_state != AppState::Playing || self.player2 == PlayerKind::Wall {
+            return false;
+        }
+        if self.time_limit.is_some() {
+            return self.time_is_up();
+        }
+        let close = |score: u32, rounds: u32| {
+            score + 1 >= WINNING_SCORE && rounds + 1 >= ROUNDS_TO_WIN
+        };
+        close(self.score1, self.rounds_won1) || close(self.score2, self.rounds_won2)
+    }
+
+    /// Tells whether a timed match has used up all of its time.
+    fn time_is_up(&self) -> bool {
+        self.time_limit.is_some_and(|limit| self.elapsed >= limit)
+    }
+
+    /// Puts the balls back in the middle column of the board, spread evenly from
+    /// top to bottom, and after the serve countdown serves each of them at an
+    /// angle towards `receiver`, or towards a randomly chosen player if there is
+    /// none.
+    pub fn reset_ball(&mut self, receiver: Option<u8>) {
+        let grid_size = self.config.grid_size;
+        // The net setting is a choice of the players, not of a particular ball.
+        let catches_at_net = self.balls.first().is_some_and(|ball| ball.catches_at_net);
+        let count = self.config.ball_count as i16;
+
+        self.balls = (1..=count)
+            .map(|number| {
+                let y = grid_size.1 * number / (count + 1);
+                let mut ball = Ball::sized((grid_size.0 / 2, y).into(), &self.config);
+                ball.serve(&mut self.rng, receiver);
+                ball.catches_at_net = catches_at_net;
+                ball
+            })
+            .collect();
+        self.begin_serve();
+    }
+}
+
+/// The padles both players start out with. Each player's team of
+/// `config.team_size` padles shares the player's edge, which is split into one
+/// lane per padle, and every padle starts out in the middle of its own lane. A
+/// lone padle has the whole edge to itself and starts where `starting_positions`
+/// puts it.
+fn starting_padles(config: &Config) -> [Vec<Padle>; 2] {
+    let (start1, start2) = starting_positions(config);
+    let length = config.difficulty.padle_length();
+    let team_size = config.team_size.max(1) as i16;
+    let height = config.grid_size.1;
+    [start1, start2].map(|start| {
+        (0..team_size)
+            .map(|member| {
+                let mut padle = Padle::new(start, length, config.padle_speed, config.grid_size);
+                if team_size > 1 {
+                    let first = height * member / team_size;
+                    let last = height * (member + 1) / team_size - 1;
+                    padle.keep_to_lane(first, last, config);
+                }
+                padle
+            })
+            .collect()
+    })
+}
+
+/// Where the bottom ends of the two padles start out. Both padles stand on the
+/// same rows, mirror images of each other across the middle of the board, and as
+/// close to vertically centered as the board's height allows. When the padle
+/// can't be exactly centered it sits half a cell high on both sides alike.
+fn starting_positions(config: &Config) -> (GridPosition, GridPosition) {
+    let (width, height) = config.grid_size;
+    let length = config.difficulty.padle_length().min(height);
+    let bottom = (height - length) / 2 + length - 1;
+    (GridPosition::new(0, bottom), GridPosition::new(width - 1, bottom))
+}
+
+/// The row a ball at `pos` flying along `velocity` will be on once it reaches
+/// `column`, if nothing but the top and bottom walls gets in its way. Each wall
+/// bounce mirrors the ball back, so we fold its straight path back onto the board
+/// the same way. On a torus it just wraps around.
+fn landing_row(pos: GridPosition, velocity: Velocity, column: i16, config: &Config) -> i16 {
+    let height = config.grid_size.1;
+    let steps = if velocity.dx == 0 {
+        0
+    } else {
+        ((column - pos.x) / velocity.dx).max(0)
+    };
+    let row = pos.y + velocity.dy * steps;
+    if config.torus {
+        return row.rem_euclid(height);
+    }
+    let period = 2 * (height - 1);
+    if period <= 0 {
+        return 0;
+    }
+    let row = row.rem_euclid(period);
+    if row >= height {
+        period - row
+    } else {
+        row
+    }
+}
+
+/// Keeps a ball `size` cells across that moved to `pos` along `velocity` on the
+/// board. The top and bottom walls reflect it, so a ball that would leave the
+/// board gets mirrored back onto it and its vertical velocity flips. On a torus
+/// there are no walls there, and the ball wraps around to the other side instead,
+/// just like `EdgeMode::Wrap` does. Also tells whether the ball bounced.
+fn bounce_off_walls(
+    mut pos: GridPosition,
+    mut velocity: Velocity,
+    size: i16,
+    config: &Config,
+) -> (GridPosition, Velocity, bool) {
+    let height = config.grid_size.1;
+    if config.torus {
+        pos.y = pos.y.rem_euclid(height);
+        return (pos, velocity, false);
+    }
+    let bounced = pos.y < 0 || pos.y > height - size;
+    if pos.y < 0 {
+        pos.y = -pos.y;
+    } else if pos.y > height - size {
+        pos.y = 2 * (height - size) - pos.y;
+    }
+    if bounced {
+        velocity.dy = -velocity.dy;
+    }
+    (pos, velocity, bounced)
+}
+
+/// The cells something moving in a straight line from `from` to `to` passes
+/// through, in order, leaving out `from` and ending with `to`. Where the line
+/// runs exactly through the corner of a cell it touches both cells beside the
+/// corner, and both are included, so nothing can slip through diagonally.
+fn swept_path(from: GridPosition, to: GridPosition) -> Vec<GridPosition> {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let (steps_x, steps_y) = (dx.abs(), dy.abs());
+    let (mut x, mut y) = (from.x, from.y);
+    let (mut taken_x, mut taken_y) = (0, 0);
+    let mut cells = Vec::new();
+    while taken_x < steps_x || taken_y < steps_y {
+        // Whichever cell border the line crosses next decides which way we step.
+        // Both at once means it goes through a corner.
+        let next = (1 + 2 * taken_x) * steps_y - (1 + 2 * taken_y) * steps_x;
+        if next == 0 {
+            cells.push(GridPosition::new(x + dx.signum(), y));
+            cells.push(GridPosition::new(x, y + dy.signum()));
+            x += dx.signum();
+            y += dy.signum();
+            taken_x += 1;
+            taken_y += 1;
+        } else if next < 0 {
+            x += dx.signum();
+            taken_x += 1;
+        } else {
+            y += dy.signum();
+            taken_y += 1;
+        }
+        cells.push(GridPosition::new(x, y));
+    }
+    cells
+}
+
+/// Places `config.obstacles` pairs of obstacles on the court. One of each pair is
+/// put at random on player 1's half, and the other is its mirror image on player
+/// 2's half, so neither player has it easier. They keep clear of the columns in
+/// front of the padles and of the center column where the ball is served.
+fn place_obstacles(rng: &mut Rand32, config: &Config) -> Vec<GridPosition> {
+    let (width, height) = config.grid_size;
+    // Player 1's half runs from the third column to just before the center, and
+    // a board too narrow to have one gets no obstacles.
+    let columns = width / 2 - 3;
+    if columns <= 0 {
+        return Vec::new();
+    }
+
+    let mut obstacles = Vec::new();
+    for _ in 0..config.obstacles {
+        let pos = GridPosition::random(rng, columns, height);
+        let pos = GridPosition::new(pos.x + 2, pos.y);
+        obstacles.push(pos);
+        obstacles.push(GridPosition::new(width - 1 - pos.x, pos.y));
+    }
+    obstacles
+}
+
+/// The color of segment `index` of a padle `length` segments long, counting from
+/// the bottom. The colors blend evenly from the bottom color of `colors` to the
+/// top one.
+fn gradient_color(index: usize, length: usize, colors: ([f32; 4], [f32; 4])) -> [f32; 4] {
+    let t = if length > 1 {
+        index as f32 / (length - 1) as f32
+    } else {
+        0.0
+    };
+    let (mut color, top) = colors;
+    for (channel, top) in color.iter_mut().zip(top) {
+        *channel += (top - *channel) * t;
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use net::Connection;
+
+    /// A lone `padle` for player 1 and a lone `other` for player 2, the way
+    /// `Ball::update` takes them.
+    fn teams<'a>(padle: &'a Padle, other: &'a Padle) -> [&'a [Padle]; 2] {
+        [std::slice::from_ref(padle), std::slice::from_ref(other)]
+    }
+
+    fn moved(x: i16, y: i16, dir: Direction, edges: EdgeMode) -> GridPosition {
+        GridPosition::new_from_move(GridPosition::new(x, y), dir, GRID_SIZE, edges)
+    }
+
+    #[test]
+    fn new_from_move_wraps_around_the_edges() {
+        let (w, h) = GRID_SIZE;
+        assert_eq!(moved(0, 5, Direction::Left, EdgeMode::Wrap), GridPosition::new(w - 1, 5));
+        assert_eq!(moved(w - 1, 5, Direction::Right, EdgeMode::Wrap), GridPosition::new(0, 5));
+        assert_eq!(moved(5, 0, Direction::Up, EdgeMode::Wrap), GridPosition::new(5, h - 1));
+        assert_eq!(moved(5, h - 1, Direction::Down, EdgeMode::Wrap), GridPosition::new(5, 0));
+    }
+
+    #[test]
+    fn new_from_move_clamps_at_the_edges() {
+        let (w, h) = GRID_SIZE;
+        assert_eq!(moved(0, 5, Direction::Left, EdgeMode::Clamp), GridPosition::new(0, 5));
+        assert_eq!(moved(w - 1, 5, Direction::Right, EdgeMode::Clamp), GridPosition::new(w - 1, 5));
+        assert_eq!(moved(5, 0, Direction::Up, EdgeMode::Clamp), GridPosition::new(5, 0));
+        assert_eq!(moved(5, h - 1, Direction::Down, EdgeMode::Clamp), GridPosition::new(5, h - 1));
+    }
+
+    #[test]
+    fn new_from_move_steps_one_cell_inside_the_board() {
+        for edges in [EdgeMode::Wrap, EdgeMode::Clamp] {
+            assert_eq!(moved(5, 5, Direction::Left, edges), GridPosition::new(4, 5));
+            assert_eq!(moved(5, 5, Direction::Right, edges), GridPosition::new(6, 5));
+            assert_eq!(moved(5, 5, Direction::Up, edges), GridPosition::new(5, 4));
+            assert_eq!(moved(5, 5, Direction::Down, edges), GridPosition::new(5, 6));
+        }
+    }
+
+    #[test]
+    fn new_from_move_without_a_direction_stays_put() {
+        for edges in [EdgeMode::Wrap, EdgeMode::Clamp] {
+            assert_eq!(moved(0, 0, Direction::None, edges), GridPosition::new(0, 0));
+        }
+    }
+
+    #[test]
+    fn ball_bounces_off_a_stationary_padle() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let mut ball = Ball::new((3, 8).into());
+
+        for _ in 0..6 {
+            ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+            assert!(!ball.is_out(&config), "ball went through at {:?}", ball.pos);
+        }
+
+        assert!(ball.velocity.dx > 0);
+        assert!(ball.pos.x > 1);
+        assert!(padle.meats_ball(&ball).is_none());
+    }
+
+    /// Plays a match from a ball served level from the middle of the board at
+    /// padle `toward`'s center row, with both padles standing still, and returns
+    /// where the ball was after every step.
+    fn serve_level(toward: u8) -> Vec<GridPosition> {
+        let mut state = GameState::with_seed(5, Config::default());
+        state.app_state = AppState::Playing;
+        state.serve_timer = 0;
+
+        let (width, row) = (state.config.grid_size.0, state.padles[0][0].center().y);
+        let (x, dx) = if toward == 1 { (width / 2, -1) } else { (width - 1 - width / 2, 1) };
+        let mut ball = Ball::new(GridPosition::new(x, row));
+        ball.velocity = Velocity::new(dx, 0);
+        state.balls = vec![ball];
+
+        (0..width * 3)
+            .map(|_| {
+                state.step();
+                state.balls[0].pos
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_level_ball_comes_straight_back_off_a_centered_padle() {
+        let trajectory = serve_level(1);
+        let start = trajectory[0];
+
+        // It never leaves its row, reaches padle 1's column and then heads back
+        // out past where it started
+        assert!(trajectory.iter().all(|pos| pos.y == start.y), "{:?}", trajectory);
+        let turn = trajectory.iter().position(|pos| pos.x == 1).unwrap();
+        assert!(trajectory[turn..].iter().any(|pos| pos.x > start.x));
+    }
+
+    #[test]
+    fn a_mirrored_serve_flies_a_mirrored_path() {
+        let width = Config::default().grid_size.0;
+        let mirrored: Vec<_> = serve_level(1)
+            .into_iter()
+            .map(|pos| GridPosition::new(width - 1 - pos.x, pos.y))
+            .collect();
+        assert_eq!(serve_level(2), mirrored);
+    }
+
+    #[test]
+    fn a_big_ball_bounces_off_the_bottom_wall_and_a_padle_it_only_clips() {
+        let config = Config {
+            ball_size: 2,
+            ..Config::default()
+        };
+        let (width, height) = config.grid_size;
+        let padle = Padle::new((0, 4).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((width - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+
+        // Both of its rows stay on the board
+        let mut ball = Ball::sized((10, height).into(), &config);
+        assert_eq!(ball.pos, GridPosition::new(10, height - 2));
+        ball.velocity = Velocity::new(1, 1);
+        ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(ball.pos, GridPosition::new(11, height - 3));
+        assert_eq!(ball.velocity.dy, -1);
+
+        // Only its top row reaches the bottom end of the padle, which still hits
+        // it and pushes it downwards
+        let mut ball = Ball::sized((1, 4).into(), &config);
+        ball.velocity = Velocity::new(-1, 0);
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert_eq!(ball.pos.x, 1);
+        assert_eq!(ball.velocity, Velocity::new(1, 1));
+    }
+
+    #[test]
+    fn swept_path_includes_the_cells_beside_a_diagonal() {
+        let cells = |x, y| GridPosition::new(x, y);
+        assert_eq!(swept_path(cells(1, 5), cells(0, 4)), [cells(0, 5), cells(1, 4), cells(0, 4)]);
+        assert_eq!(swept_path(cells(0, 0), cells(3, 0)), [cells(1, 0), cells(2, 0), cells(3, 0)]);
+        assert_eq!(swept_path(cells(0, 0), cells(2, 1)), [cells(1, 0), cells(1, 1), cells(2, 1)]);
+    }
+
+    #[test]
+    fn a_fast_diagonal_ball_cannot_slip_past_the_corner_of_a_short_padle() {
+        let config = Config {
+            max_ball_speed: 4.0,
+            ..Config::default()
+        };
+        let padle = Padle::new((0, 5).into(), 1, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+
+        // In one update the ball flies from (4, 8) to (0, 4), and on its last step
+        // it crosses the corner of the padle's only segment.
+        let mut ball = Ball::new((4, 8).into());
+        ball.velocity = Velocity::new(-1, -1);
+        ball.speed = 4.0;
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert!(ball.velocity.dx > 0);
+        assert!(!ball.is_out(&config));
+    }
+
+    #[test]
+    fn ball_reaching_the_padle_column_bounces_rather_than_scores() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let mut ball = Ball::new((1, 8).into());
+
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert!(!ball.is_out(&config));
+        assert!(ball.velocity.dx > 0);
+    }
+
+    #[test]
+    fn ball_on_the_padle_column_bounces_once_the_padle_covers_it() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let mut ball = Ball::new((0, 8).into());
+        assert!(!ball.is_out(&config));
+
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert_eq!(ball.pos, GridPosition::new(1, 8));
+        assert!(ball.velocity.dx > 0);
+    }
+
+    #[test]
+    fn ball_only_scores_after_crossing_the_score_plane() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        // The padle covers rows 6 to 10, so this ball misses it.
+        let mut ball = Ball::new((1, 2).into());
+
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::None);
+        assert_eq!(ball.pos.x, 0);
+        assert!(!ball.is_out(&config));
+
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::ScoredOn(1));
+        assert!(ball.is_out(&config));
+    }
+
+    #[test]
+    fn ball_bounces_off_the_right_wall_without_a_second_padle() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let mut ball = Ball::new((config.grid_size.0 - 3, 8).into());
+        ball.velocity = Direction::Right.into();
+
+        for _ in 0..4 {
+            let teams = [std::slice::from_ref(&padle), &[]];
+            ball.update(teams, &[], &mut heat_map, &mut None, 1.0, &config);
+            assert!(!ball.is_out(&config), "ball went through at {:?}", ball.pos);
+        }
+
+        assert!(ball.velocity.dx < 0);
+    }
+
+    #[test]
+    fn a_flat_padle_moves_sideways_until_it_reaches_the_wall() {
+        let config = Config::default();
+        let mut padle = Padle::with_orientation(
+            GridPosition::new(5, 0),
+            3,
+            1.0,
+            Orientation::Horizontal,
+            config.grid_size,
+        );
+        assert_eq!(padle.center(), GridPosition::new(4, 0));
+        assert!(!padle.can_move(Direction::Down, &config));
+
+        padle.press(Direction::Left);
+        for _ in 0..10 {
+            padle.update(&config);
+        }
+        let mut cells: Vec<_> = padle.cells().collect();
+        cells.sort_by_key(|pos| pos.x);
+        assert_eq!(cells, [(0, 0).into(), (1, 0).into(), (2, 0).into()]);
+    }
+
+    #[test]
+    fn a_flat_padle_sends_the_ball_back_up_and_the_computer_steers_it_sideways() {
+        let config = Config::default();
+        let flat = Padle::with_orientation(
+            GridPosition::new(12, 12),
+            5,
+            1.0,
+            Orientation::Horizontal,
+            config.grid_size,
+        );
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+
+        // A ball falling onto the flat padle bounces back up and stays above it
+        let mut ball = Ball::new((10, 11).into());
+        ball.velocity = Velocity::new(1, 1);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let padles = teams(&flat, &other);
+        let event = ball.update(padles, &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert_eq!(ball.velocity, Velocity::new(1, -1));
+        assert_eq!(ball.pos.y, flat.center().y - 1);
+
+        // The computer moves a flat padle towards the ball's column
+        let mut state = GameState::with_seed(1, config);
+        state.padles[1] = vec![flat];
+        state.ai_aim_error = 0;
+        state.balls[0].pos = GridPosition::new(3, 5);
+        state.update_ai(2);
+        assert_eq!(state.padles[1][0].dir, Direction::Left);
+    }
+
+    #[test]
+    fn quick_taps_between_updates_each_move_the_padle() {
+        let config = Config::default();
+        let mut padle = Padle::new(GridPosition::new(0, 10), 3, 1.0, config.grid_size);
+        for _ in 0..2 {
+            padle.press(Direction::Down);
+            padle.release(Direction::Down);
+        }
+        padle.press(Direction::Up);
+        padle.release(Direction::Up);
+
+        // The padle starts centered on row 9 and takes one step per tap, then
+        // stays put since no key is held anymore
+        for row in [10, 11, 10, 10] {
+            padle.update(&config);
+            assert_eq!(padle.center(), GridPosition::new(0, row));
+        }
+    }
+
+    #[test]
+    fn padles_start_as_mirror_images_of_each_other() {
+        for difficulty in [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard] {
+            for grid_size in [(30, 20), (30, 21), (11, 8), (12, 9)] {
+                let config = Config {
+                    grid_size,
+                    difficulty,
+                    ..Config::default()
+                };
+                let state = GameState::with_seed(1, config);
+                let mirrored: Vec<_> = state
+                    .padles[0][0]
+                    .body
+                    .iter()
+                    .map(|seg| GridPosition::new(grid_size.0 - 1 - seg.pos.x, seg.pos.y))
+                    .collect();
+                let body2: Vec<_> = state.padles[1][0].body.iter().map(|seg| seg.pos).collect();
+                assert_eq!(mirrored, body2, "{:?} on {:?}", difficulty, grid_size);
+
+                // The rows above and below the padle differ by at most one
+                let top = state.padles[0][0].body.iter().map(|seg| seg.pos.y).min().unwrap();
+                let bottom = state.padles[0][0].body.iter().map(|seg| seg.pos.y).max().unwrap();
+                let (above, below) = (top, grid_size.1 - 1 - bottom);
+                assert!(below == above || below == above + 1, "{:?} on {:?}", difficulty, grid_size);
+            }
+        }
+    }
+
+    #[test]
+    fn landing_row_folds_wall_bounces_back_onto_the_board() {
+        // The default board is 20 rows high
+        let config = Config::default();
+        let landing = |x, y, dx, dy, column| {
+            landing_row(GridPosition::new(x, y), Velocity::new(dx, dy), column, &config)
+        };
+        // Straight across, without reaching a wall
+        assert_eq!(landing(10, 5, -1, 0, 0), 5);
+        // Down into the bottom wall and back up again
+        assert_eq!(landing(10, 15, 1, 1, 20), 13);
+        // Up off the top wall
+        assert_eq!(landing(10, 2, -1, -1, 0), 8);
+    }
+
+    #[test]
+    fn on_a_torus_the_ball_wraps_around_but_the_padles_still_stop() {
+        let config = Config {
+            torus: true,
+            ..Config::default()
+        };
+        let (width, height) = config.grid_size;
+        let padle = Padle::new((0, 4).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((width - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+
+        let mut ball = Ball::new((10, 0).into());
+        ball.velocity = Velocity::new(1, -1);
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::None);
+        assert_eq!(ball.pos, GridPosition::new(11, height - 1));
+        assert_eq!(ball.velocity.dy, -1);
+
+        assert!(!padle.can_move(Direction::Up, &config));
+    }
+
+    #[test]
+    fn balls_never_start_on_a_padle() {
+        let config = Config {
+            ball_count: 3,
+            ball_size: 2,
+            ..Config::default()
+        };
+        for seed in 0..500 {
+            let state = GameState::with_seed(seed, config);
+            let mut padle_cells = state.padles[0][0].cells().chain(state.padles[1][0].cells());
+            assert!(
+                !padle_cells.any(|cell| state.balls.iter().any(|ball| ball.covers(cell))),
+                "a ball started on a padle with seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn the_first_serve_goes_to_either_player_about_as_often() {
+        let to_player1 = (0..1000)
+            .filter(|&seed| GameState::with_seed(seed, Config::default()).balls[0].velocity.dx < 0)
+            .count();
+        assert!((400..=600).contains(&to_player1), "{} of 1000 went to player 1", to_player1);
+    }
+
+    #[test]
+    fn alternating_serves_switch_sides_every_two_points() {
+        let config = Config {
+            serve_rule: ServeRule::Alternate,
+            ..Config::default()
+        };
+        let mut state = GameState::with_seed(3, config);
+        let mut receivers = Vec::new();
+        for _ in 0..6 {
+            state.check_score(&[BallEvent::ScoredOn(1)]);
+            receivers.push(if state.balls[0].velocity.dx < 0 { 1 } else { 2 });
+        }
+        assert_eq!(receivers, [2, 1, 1, 2, 2, 1]);
+    }
+
+    #[test]
+    fn step_holds_the_ball_during_the_serve_countdown() {
+        let mut state = GameState::with_seed(7, Config::default());
+        let start = state.balls[0].pos;
+
+        for _ in 0..state.config.ticks(SERVE_COUNTDOWN_TICKS) {
+            state.step();
+        }
+        assert_eq!(state.balls[0].pos, start);
+
+        state.step();
+        assert_ne!(state.balls[0].pos, start);
+    }
+
+    #[test]
+    fn step_plays_until_somebody_wins() {
+        let mut state = GameState::with_seed(7, Config::default());
+        state.app_state = AppState::Playing;
+
+        // Padle 1 hides in the top corner and padle 2 doesn't move, so the ball gets
+        // past one of them sooner or later.
+        state.padles[0][0].press(Direction::Up);
+        for _ in 0..100_000 {
+            if state.app_state == AppState::GameOver {
+                break;
+            }
+            state.step();
+        }
+
+        assert_eq!(state.app_state, AppState::GameOver);
+        assert_eq!(state.score1.max(state.score2), WINNING_SCORE);
+        assert_eq!(state.rounds_won1.max(state.rounds_won2), ROUNDS_TO_WIN);
+        assert_eq!(state.match_winner(), state.winner());
+
+        // R starts the next match right away with the same players
+        let seed = state.recording.seed;
+        state.key_down(Key::R);
+        assert_eq!(state.app_state, AppState::Playing);
+        assert_eq!((state.score1, state.score2), (0, 0));
+        assert_eq!(state.match_winner(), None);
+        assert_ne!(state.recording.seed, seed);
+    }
+
+    #[test]
+    fn a_timed_match_on_a_tie_goes_to_sudden_death() {
+        let config = Config {
+            time_limit: Some(1),
+            ..Config::default()
+        };
+        let mut state = GameState::with_seed(7, config);
+        state.app_state = AppState::Playing;
+        (state.score1, state.score2) = (1, 1);
+        state.serve_timer = 0;
+        state.elapsed = Duration::from_millis(990);
+
+        // The clock runs out on a tie, so the match goes on
+        state.step();
+        assert!(state.time_is_up());
+        assert_eq!(state.app_state, AppState::Playing);
+
+        // and the next point decides it
+        state.check_score(&[BallEvent::ScoredOn(2)]);
+        assert_eq!(state.app_state, AppState::GameOver);
+        assert_eq!(state.match_winner(), Some(1));
+    }
+
+    #[test]
+    fn the_clock_stops_while_a_timed_match_waits_for_a_serve() {
+        let config = Config {
+            time_limit: Some(60),
+            manual_serve: true,
+            ..Config::default()
+        };
+        let mut state = GameState::with_seed(7, config);
+        state.app_state = AppState::Playing;
+        state.begin_serve();
+        state.server = 1;
+
+        // Nobody serves, so the clock stays where it was
+        for _ in 0..config.tick_rate * 3 {
+            state.step();
+        }
+        assert!(state.waiting_for_serve);
+        assert_eq!(state.elapsed, Duration::ZERO);
+
+        // and neither does the countdown before an automatic serve use any up
+        state.config.manual_serve = false;
+        state.waiting_for_serve = false;
+        state.begin_serve();
+        while state.serve_timer > 0 {
+            state.step();
+        }
+        assert_eq!(state.elapsed, Duration::ZERO);
+
+        // but once the ball is in play it runs
+        state.step();
+        assert!(state.elapsed > Duration::ZERO);
+    }
+
+    #[test]
+    fn the_game_state_dumps_to_json() {
+        let state = GameState::with_seed(7, Config::default());
+        let json = serde_json::to_value(&state).unwrap();
+        let pos = state.balls[0].pos;
+        assert_eq!(json["balls"][0]["pos"], serde_json::json!({ "x": pos.x, "y": pos.y }));
+        let body = json["padles"][0][0]["body"].as_array().unwrap();
+        assert_eq!(body.len(), state.padles[0][0].body.len());
+    }
+
+    #[test]
+    fn a_recorded_match_replays_to_the_same_score() {
+        let mut state = GameState::with_seed(42, Config::default());
+
+        // Start a match against the computer and wiggle padle 1 up and down every
+        // so often, so the recording has something in it.
+        state.key_down(Key::Return);
+        for _ in 0..200_000 {
+            if state.app_state == AppState::GameOver {
+                break;
+            }
+            match state.tick % 60 {
+                0 => state.key_down(Key::W),
+                10 => state.key_up(Key::W),
+                30 => state.key_down(Key::S),
+                40 => state.key_up(Key::S),
+                _ => (),
+            }
+            state.advance();
+        }
+        assert_eq!(state.app_state, AppState::GameOver);
+
+        // The replay goes through JSON just like when it is saved to a file.
+        let json = serde_json::to_string(&state.recording).unwrap();
+        let mut replayed = GameState::from_replay(serde_json::from_str(&json).unwrap());
+        while replayed.app_state != AppState::GameOver && replayed.tick <= state.tick {
+            replayed.advance();
+        }
+
+        assert_eq!(replayed.tick, state.tick);
+        assert_eq!((replayed.score1, replayed.score2), (state.score1, state.score2));
+        assert_eq!(
+            (replayed.rounds_won1, replayed.rounds_won2),
+            (state.rounds_won1, state.rounds_won2)
+        );
+    }
+
+    #[test]
+    fn the_ball_moves_as_fast_at_any_tick_rate() {
+        // We let the ball fly along a row the padles are nowhere near for one
+        // second, and see how far it got.
+        let distance = |tick_rate: u32| {
+            let config = Config {
+                tick_rate,
+                ..Config::default()
+            };
+            let padle = Padle::new((0, 2).into(), 3, 1.0, config.grid_size);
+            let other = Padle::new((config.grid_size.0 - 1, 2).into(), 3, 1.0, config.grid_size);
+            let mut heat_map = HeatMap::new(config.grid_size);
+            let mut ball = Ball::new((2, 15).into());
+            ball.velocity = Direction::Right.into();
+
+            let elapsed = config.tick_scale();
+            for _ in 0..tick_rate {
+                ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, elapsed, &config);
+            }
+            ball.pos.x - 2
+        };
+
+        // Floating point rounding may leave the last cell not quite reached.
+        let expected = DESIRED_FPS as i16;
+        for tick_rate in [30, 60] {
+            let moved = distance(tick_rate);
+            assert!(
+                (expected - 1..=expected).contains(&moved),
+                "moved {} cells at {} ticks",
+                moved,
+                tick_rate
+            );
+        }
+    }
+
+    #[test]
+    fn the_ghost_is_where_the_ball_ends_up_after_a_wall_bounce() {
+        let config = Config::default();
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+        let mut ball = Ball::new((10, 2).into());
+        ball.velocity = Velocity::new(1, -1);
+
+        let ghost = ball.predicted_pos(6, &config);
+        for _ in 0..6 {
+            ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        }
+
+        assert_eq!(ghost, ball.pos);
+        assert_eq!(ghost, GridPosition::new(16, 4));
+    }
+
+    #[test]
+    fn teammates_keep_to_their_lanes_and_both_defend_their_edge() {
+        let config = Config {
+            team_size: 2,
+            ..Config::default()
+        };
+        let mut state = GameState::with_seed(3, config);
+        state.app_state = AppState::Playing;
+        state.serve_timer = 0;
+        state.balls.clear();
+
+        // Pushed towards each other, the two padles on the left never overlap
+        state.padles[0][0].press(Direction::Down);
+        state.padles[0][1].press(Direction::Up);
+        for _ in 0..config.grid_size.1 {
+            state.step();
+        }
+        let bottom_of_top = state.padles[0][0].cells().map(|cell| cell.y).max().unwrap();
+        let top_of_bottom = state.padles[0][1].cells().map(|cell| cell.y).min().unwrap();
+        assert!(bottom_of_top < top_of_bottom);
+
+        // A ball coming in low is sent back by the lower padle
+        let row = state.padles[0][1].center().y;
+        let mut ball = Ball::new(GridPosition::new(3, row));
+        ball.velocity = Velocity::new(-1, 0);
+        state.balls = vec![ball];
+        for _ in 0..4 {
+            state.step();
+        }
+        assert!(state.balls[0].velocity.dx > 0);
+        assert_eq!(state.score(), (0, 0));
+    }
+
+    #[test]
+    fn a_ball_at_top_speed_still_bounces_off_a_padle() {
+        let config = Config {
+            max_ball_speed: 6.0,
+            ..Config::default()
+        };
+        let padle = Padle::new((0, 10).into(), 5, 1.0, config.grid_size);
+        let other = Padle::new((config.grid_size.0 - 1, 10).into(), 5, 1.0, config.grid_size);
+        let mut heat_map = HeatMap::new(config.grid_size);
+
+        // However often it is hit, the ball gets no faster than the cap
+        let mut ball = Ball::new((4, 8).into());
+        for _ in 0..100 {
+            ball.speed_up(&config);
+        }
+        assert_eq!(ball.speed, config.max_ball_speed);
+
+        // and in one update it would fly well past the padle, but gets hit instead
+        let event = ball.update(teams(&padle, &other), &[], &mut heat_map, &mut None, 1.0, &config);
+        assert_eq!(event, BallEvent::HitPaddle(1, 0));
+        assert!(!ball.is_out(&config));
+        assert!(ball.velocity.dx > 0);
+    }
+
+    #[test]
+    fn a_saved_match_goes_on_just_like_the_original() {
+        let path = std::env::temp_dir().join("moving_paddles_test_saved_match.json");
+        let mut state = GameState::with_seed(11, Config::default());
+        state.app_state = AppState::Playing;
+        state.padles[0][0].press(Direction::Up);
+        for _ in 0..500 {
+            state.step();
+        }
+
+        state.save(&path).unwrap();
+        let mut resumed = GameState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The RNG came back too, so both play the same points from here on
+        for _ in 0..2000 {
+            state.step();
+            resumed.step();
+        }
+        assert_eq!(resumed.score(), state.score());
+        assert_eq!(resumed.balls[0].pos, state.balls[0].pos);
+    }
+
+    #[test]
+    fn a_save_from_another_version_is_refused() {
+        let path = std::env::temp_dir().join("moving_paddles_test_old_save.json");
+        std::fs::write(&path, r#"{ "version": 0, "state": {} }"#).unwrap();
+        let loaded = GameState::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn a_save_with_things_off_the_board_is_refused() {
+        let path = std::env::temp_dir().join("moving_paddles_test_broken_save.json");
+        let mut state = GameState::with_seed(3, Config::default());
+        state.balls[0].pos = GridPosition::new(GRID_SIZE.0 + 5, 2);
+        state.save(&path).unwrap();
+        let ball_off = GameState::load(&path).map(|_| ());
+
+        let mut state = GameState::with_seed(3, Config::default());
+        state.config.grid_size = (2, 2);
+        state.save(&path).unwrap();
+        let board_too_small = GameState::load(&path).map(|_| ());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ball_off.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(board_too_small.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn a_replay_that_cannot_be_saved_is_left_for_the_front_end_to_report() {
+        // A file can't have anything inside it, so nothing can be saved there
+        let blocker = std::env::temp_dir().join("moving_paddles_test_not_a_dir");
+        std::fs::write(&blocker, "").unwrap();
+        let mut state = GameState::with_seed(3, Config::default());
+        state.replay_path = Some(blocker.join("replay.json"));
+        state.finish_match(1);
+        std::fs::remove_file(&blocker).unwrap();
+
+        let notices = state.take_notices();
+        assert_eq!(notices.len(), 1);
+        assert!(notices[0].starts_with("Could not save the replay"), "{}", notices[0]);
+        assert!(state.take_notices().is_empty());
+    }
+
+    #[test]
+    fn a_save_pointing_past_the_themes_menus_or_players_is_refused() {
+        let path = std::env::temp_dir().join("moving_paddles_test_bad_indices.json");
+        let breakages: [fn(&mut GameState); 5] = [
+            |state| state.theme_index = THEMES.len(),
+            |state| state.menu_selection = MENU_OPTIONS.len(),
+            |state| state.settings_selection = Some(SETTINGS.len()),
+            |state| state.flash_player = 0,
+            |state| state.server = 3,
+        ];
+        for (index, breakage) in breakages.iter().enumerate() {
+            let mut state = GameState::with_seed(3, Config::default());
+            breakage(&mut state);
+            state.save(&path).unwrap();
+            let loaded = GameState::load(&path).map(|_| ());
+            assert_eq!(
+                loaded.err().map(|e| e.kind()),
+                Some(io::ErrorKind::InvalidData),
+                "breakage {}",
+                index
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_board_from_the_host_that_makes_no_sense_is_refused() {
+        let state = GameState::with_seed(3, Config::default());
+        let snapshot = state.snapshot(None);
+        assert_eq!(state.check_snapshot(&snapshot), Ok(()));
+
+        let (width, height) = state.config.grid_size;
+        let breakages: [fn(&mut Snapshot, i16, i16); 6] = [
+            |snapshot, width, _| snapshot.balls[0].0.x = width,
+            |snapshot, _, height| snapshot.balls[0].0.y = height,
+            |snapshot, _, _| snapshot.padles[0][0].1 = 0,
+            |snapshot, _, _| snapshot.padles[1].clear(),
+            |snapshot, width, _| snapshot.obstacles.push(GridPosition::new(width, 0)),
+            |snapshot, _, _| snapshot.flash.1 = 0,
+        ];
+        for (index, breakage) in breakages.iter().enumerate() {
+            let mut broken = snapshot.clone();
+            breakage(&mut broken, width, height);
+            assert!(state.check_snapshot(&broken).is_err(), "breakage {}", index);
+        }
+    }
+
+    #[test]
+    fn a_tap_from_the_player_who_joined_moves_the_hosts_padle() {
+        let listener = Connection::listen(0).unwrap();
+        let address = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let mut joined = Connection::join(&address).unwrap();
+        let hosting = Connection::host(&listener).unwrap();
+
+        let mut state = GameState::with_seed(3, Config::default());
+        state.peer = Some(Peer::Host(hosting));
+        state.menu_selection = 1;
+        state.start_match();
+        let start = state.padles[1][0].center();
+
+        // The key went down and up again between two updates
+        let tap = vec![(Direction::Down, true), (Direction::Down, false)];
+        joined.send(&Message::Input(tap)).unwrap();
+        state.advance();
+
+        assert_eq!(state.padles[1][0].center().y, start.y + 1);
+        assert_eq!(state.padles[1][0].dir, Direction::None);
+        assert!(matches!(joined.receive(), Ok(Message::Snapshot(_))));
+    }
+
+    #[test]
+    fn replays_are_checked_when_they_are_loaded() {
+        let path = std::env::temp_dir().join("moving_paddles_test_replay.json");
+        let mut replay = Replay::new(8, Config::default());
+        replay.record(3, Key::W, true);
+        replay::save_replay(&path, &replay).unwrap();
+        let loaded = replay::load_replay(&path);
+
+        // One written before replays had a version is refused
+        let json = std::fs::read_to_string(&path).unwrap().replace("\"version\": 1,", "");
+        std::fs::write(&path, json).unwrap();
+        let unversioned = replay::load_replay(&path);
+
+        // and so is one with settings the command line wouldn't take
+        let mut too_many_balls = replay.clone();
+        too_many_balls.config.ball_count = 50;
+        replay::save_replay(&path, &too_many_balls).unwrap();
+        let too_many_balls = replay::load_replay(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), replay);
+        assert_eq!(unversioned.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+        assert_eq!(too_many_balls.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn padles_of_impossible_lengths_are_brought_onto_the_board() {
+        // On the default board and on one only 8 rows high
+        for grid_size in [GRID_SIZE, (10, 8)] {
+            let height = grid_size.1;
+            for (length, expected) in [(0, 1), (-3, 1), (height + 10, height), (9, 9.min(height))] {
+                let padle = Padle::new(GridPosition::new(0, height - 1), length, 1.0, grid_size);
+                assert_eq!(padle.length(), expected, "asked for {}", length);
+                assert!(padle.cells().all(|cell| (0..height).contains(&cell.y)));
+
+                // and the ball still finds it
+                let ball = Ball::new(padle.center());
+                assert!(padle.meats_ball(&ball).is_some(), "asked for {}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn sparks_fly_on_every_hit_but_never_pile_up() {
+        let mut state = GameState::with_seed(5, Config::default());
+        state.app_state = AppState::Playing;
+        state.serve_timer = 0;
+
+        // A level ball at the padles' center row goes back and forth for good
+        let row = state.padles[0][0].center().y;
+        let mut ball = Ball::new(GridPosition::new(state.config.grid_size.0 / 2, row));
+        ball.velocity = Velocity::new(-1, 0);
+        state.balls = vec![ball];
+
+        let mut most = 0;
+        for _ in 0..2000 {
+            state.step();
+            most = most.max(state.particles.len());
+        }
+        assert!(most > 0);
+        assert!(most <= MAX_PARTICLES);
+    }
+
+    #[test]
+    fn a_ball_served_by_hand_waits_for_the_server() {
+        let config = Config {
+            manual_serve: true,
+            ..Config::default()
+        };
+        let mut state = GameState::with_seed(9, config);
+        state.menu_selection = 1;
+        state.key_down(Key::Return);
+        let start = state.balls[0].pos;
+        for _ in 0..100 {
+            state.step();
+        }
+        assert_eq!(state.balls[0].pos, start);
+
+        // The server's up key sends it off upwards, towards the other player
+        let up = if state.server == 1 { Key::W } else { Key::Up };
+        state.key_down(up);
+        state.step();
+        assert_ne!(state.balls[0].pos, start);
+        assert_eq!(state.balls[0].velocity.dy, -1);
+        assert_eq!(state.balls[0].velocity.dx.signum(), if state.server == 1 { 1 } else { -1 });
+    }
+
+    #[test]
+    fn a_key_bound_to_two_players_is_rejected() {
+        let path = std::env::temp_dir().join("moving_paddles_test_shared_key.json");
+        let json = r#"{
+            "player1": { "up": "W", "down": "S" },
+            "player2": { "up": "Up", "down": "S" }
+        }"#;
+        std::fs::write(&path, json).unwrap();
+        let loaded = keybindings::load_key_bindings(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = loaded.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("more than one player"), "{}", error);
+    }
+
+    #[test]
+    fn tab_comes_back_to_the_keys_from_the_file() {
+        let path = std::env::temp_dir().join("moving_paddles_test_own_keys.json");
+        let json = r#"{
+            "player1": { "up": "Q", "down": "A" },
+            "player2": { "up": "O", "down": "L" }
+        }"#;
+        std::fs::write(&path, json).unwrap();
+        let own = keybindings::load_key_bindings(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut state = GameState::with_seed(3, Config::default());
+        state.key_bindings = own.clone();
+        state.own_key_bindings = Some(own.clone());
+
+        let mut seen = Vec::new();
+        for _ in 0..=keybindings::PRESETS.len() {
+            state.key_down(Key::Tab);
+            seen.push(state.key_bindings.preset_index());
+        }
+        assert_eq!(seen, [Some(0), Some(1), None]);
+        assert_eq!(state.key_bindings, own);
+    }
+
+    #[test]
+    fn without_a_key_bindings_file_players_get_w_s_and_the_arrows() {
+        let path = std::env::temp_dir().join("moving_paddles_test_no_such_keybindings.json");
+        let bindings = keybindings::load_key_bindings(&path).unwrap();
+        assert_eq!(bindings, KeyBindings::default());
+        assert_eq!(bindings.get(Key::W), Some((1, Direction::Up)));
+        assert_eq!(bindings.get(Key::S), Some((1, Direction::Down)));
+        assert_eq!(bindings.get(Key::Up), Some((2, Direction::Up)));
+        assert_eq!(bindings.get(Key::Down), Some((2, Direction::Down)));
+    }
+}
@@ -0,0 +1,204 @@
+// Two instances of the game can play against each other over TCP. One of them
+// hosts: it runs the whole game and after every update sends the other one a
+// `Snapshot` of the board. The other one joins: it only draws the snapshots it
+// gets and sends back which way its player wants the padle to go. The two take
+// turns once per update, so they stay in lockstep and neither runs ahead.
+//
+// Every message is a line of JSON. A background thread reads the lines as they
+// come in, so the game itself only ever waits when it needs the next message.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::sounds::Sound;
+use crate::{AppState, Direction, GridPosition, PowerUp, Velocity};
+
+// When the other side hasn't said anything for this long, we give up on it.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the players asked for on the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// Wait for another player on this port
+    Host(u16),
+    /// Join the game hosted at this address, like `localhost:7777`
+    Join(String),
+}
+
+/// Everything that goes over the connection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Message {
+    /// The first thing the host sends, so both sides build the same board
+    Hello { seed: u64, config: Config },
+    /// The keys the joining player pressed (`true`) and let go (`false`) since
+    /// the last update, in order, for the host to play on their padle
+    Input(Vec<(Direction, bool)>),
+    /// The board after the host's update
+    Snapshot(Snapshot),
+}
+
+/// The parts of the host's game the joining side needs to draw it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Where every ball is and where it is heading
+    pub(crate) balls: Vec<(GridPosition, Velocity)>,
+    /// The center row and the length of each padle, team by team
+    pub(crate) padles: [Vec<(i16, i16)>; 2],
+    pub(crate) score: (u32, u32),
+    pub(crate) rounds: (u32, u32),
+    /// The current rally and the longest one of the match
+    pub(crate) rallies: (u32, u32),
+    /// How long the match has been played for
+    pub(crate) elapsed: Duration,
+    /// How much longer the flash for the last point lasts, and who won it
+    pub(crate) flash: (u32, u8),
+    /// How much longer each padle stays lit up from hitting the ball
+    pub(crate) highlights: [Vec<u32>; 2],
+    pub(crate) app_state: AppState,
+    pub(crate) paused: bool,
+    pub(crate) serve_timer: u32,
+    pub(crate) obstacles: Vec<GridPosition>,
+    pub(crate) power_up: Option<PowerUp>,
+    /// The sound the update made, so both players hear it
+    pub(crate) sound: Option<Sound>,
+}
+
+/// Which end of a connection this game is.
+pub enum Peer {
+    Host(Connection),
+    Join(Connection),
+}
+
+impl Peer {
+    /// The connection to the other end, whichever end we are.
+    pub fn connection(&mut self) -> &mut Connection {
+        match self {
+            Peer::Host(connection) | Peer::Join(connection) => connection,
+        }
+    }
+}
+
+/// A connection to the other instance of the game.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Receiver<io::Result<Message>>,
+}
+
+impl Connection {
+    /// Opens `port` for another player to join on. Once it is open the caller
+    /// can tell the players so, and then wait for them with `host`.
+    pub fn listen(port: u16) -> io::Result<TcpListener> {
+        TcpListener::bind(("0.0.0.0", port))
+    }
+
+    /// Waits on `listener` until another player joins.
+    pub fn host(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Connection::new(stream)
+    }
+
+    /// Joins the game hosted at `address`.
+    pub fn join(address: &str) -> io::Result<Self> {
+        Connection::new(TcpStream::connect(address)?)
+    }
+
+    /// Starts the thread that reads the messages coming in on `stream`. It stops
+    /// once the connection closes, the other side sends something that isn't a
+    /// message, or the `Connection` is dropped and nobody listens anymore.
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // Our messages are small and we wait for every answer, so they should go
+        // out right away.
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let message = match line.map(|line| serde_json::from_str(&line)) {
+                    Ok(Ok(message)) => Ok(message),
+                    Ok(Err(e)) => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the other player sent a message that makes no sense: {}", e),
+                    )),
+                    Err(_) => break,
+                };
+                let garbled = message.is_err();
+                if sender.send(message).is_err() || garbled {
+                    break;
+                }
+            }
+        });
+        Ok(Connection { stream, incoming })
+    }
+
+    /// Sends `message` to the other side.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.stream.write_all(&line)
+    }
+
+    /// Waits for the next message from the other side.
+    pub fn receive(&self) -> io::Result<Message> {
+        self.incoming.recv_timeout(TIMEOUT).map_err(|e| match e {
+            RecvTimeoutError::Timeout => {
+                io::Error::new(io::ErrorKind::TimedOut, "the other player stopped answering")
+            }
+            RecvTimeoutError::Disconnected => {
+                io::Error::new(io::ErrorKind::ConnectionAborted, "the other player left")
+            }
+        })?
+    }
+
+    /// Waits for the host's `Hello` and returns the seed and settings in it. We
+    /// can't build a board from settings the command line wouldn't have taken, so
+    /// then we hang up instead.
+    pub fn receive_hello(&self) -> io::Result<(u64, Config)> {
+        match self.receive()? {
+            Message::Hello { seed, config } => match config.validate() {
+                Ok(()) => Ok((seed, config)),
+                Err(e) => {
+                    // The reading thread holds on to the stream too, so dropping
+                    // ours isn't enough to close the connection.
+                    let _ = self.stream.shutdown(Shutdown::Both);
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("the host sent settings we can't play: {}", e),
+                    ))
+                }
+            },
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the host didn't start with a hello",
+            )),
+        }
+    }
+}
+
+/// Takes `--host PORT` or `--join ADDRESS` out of the command-line arguments, if
+/// one of them is there. The remaining arguments are left for `Config`.
+pub fn take_net_arg(args: &mut Vec<String>) -> Result<Option<Role>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--host" || arg == "--join") else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(format!("{} needs a value", args[index]));
+    }
+    let value = args.remove(index + 1);
+    let role = if args.remove(index) == "--host" {
+        let port = value
+            .parse()
+            .map_err(|_| format!("--host expects a port number, got {}", value))?;
+        Role::Host(port)
+    } else {
+        Role::Join(value)
+    };
+    Ok(Some(role))
+}
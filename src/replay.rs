@@ -0,0 +1,150 @@
+// A replay holds everything needed to play a match again exactly as it went: the
+// seed and settings the game started from, and every key that went down or up
+// along with the tick it happened at. The game logic depends on nothing else, so
+// feeding the keys back in at the same ticks ends in the same score, as long as
+// the key bindings haven't changed in the meantime. Only the keyboard is
+// recorded, so a match played with the mouse or a gamepad won't replay
+// faithfully. Like a save, the file says which version of the format it was
+// written in, and a replay from another version is refused.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::keybindings::{self, Key};
+
+// The version of the replay format this build writes and understands.
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    /// The seed of the game's RNG
+    pub seed: u64,
+    /// The settings the match was played with
+    pub config: Config,
+    /// Every key change, in the order they happened
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Input {
+    /// How many steps the game had taken when the key changed
+    pub tick: u64,
+    /// The key, saved by its name
+    #[serde(with = "key_by_name")]
+    pub key: Key,
+    /// Whether the key went down rather than up
+    pub pressed: bool,
+}
+
+impl Replay {
+    /// Starts an empty recording of a game created from `seed` and `config`.
+    pub fn new(seed: u64, config: Config) -> Self {
+        Replay {
+            seed,
+            config,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Records a key change at `tick`. Keys without a name don't do anything in
+    /// the game, so they are left out.
+    pub fn record(&mut self, tick: u64, key: Key, pressed: bool) {
+        if keybindings::key_name(key).is_some() {
+            self.inputs.push(Input { tick, key, pressed });
+        }
+    }
+}
+
+/// The file as it is written.
+#[derive(Serialize)]
+struct SavedReplayRef<'a> {
+    version: u32,
+    #[serde(flatten)]
+    replay: &'a Replay,
+}
+
+/// The version of a file we read, before we know whether we can read the rest.
+/// Replays from before there were versions don't have one, so they count as
+/// version 0.
+#[derive(Deserialize)]
+pub struct ReplayHeader {
+    #[serde(default)]
+    pub version: u32,
+}
+
+/// Replays save keys by the names the key bindings file uses, so they read the
+/// same way.
+mod key_by_name {
+    use serde::{de, ser, Deserialize, Deserializer, Serializer};
+
+    use crate::keybindings::{self, Key};
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = keybindings::key_name(*key)
+            .ok_or_else(|| ser::Error::custom(format!("{:?} has no name", key)))?;
+        serializer.serialize_str(name)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        keybindings::key_from_name(&name).map_err(de::Error::custom)
+    }
+}
+
+/// Takes `--replay FILE` out of the command-line arguments, if it is there, and
+/// returns the file. The remaining arguments are left for `Config`.
+pub fn take_replay_arg(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let Some(index) = args.iter().position(|arg| arg == "--replay") else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err("--replay needs a value".to_string());
+    }
+    let path = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(PathBuf::from(path)))
+}
+
+/// Where the last finished match is saved, which is `last_replay.json` in the user
+/// data directory. There might not be one on exotic systems, in which case we
+/// return `None`.
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "moving_paddles")
+        .map(|dirs| dirs.data_dir().join("last_replay.json"))
+}
+
+/// Reads a replay from `path`. A replay from another version of the format is
+/// an error, and so is one with settings the command line wouldn't have taken.
+pub fn load_replay(path: &Path) -> io::Result<Replay> {
+    let json = fs::read_to_string(path)?;
+    let ReplayHeader { version } = serde_json::from_str(&json).map_err(io::Error::from)?;
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the replay is from version {} of the format, not {}", version, VERSION),
+        ));
+    }
+    let replay: Replay = serde_json::from_str(&json).map_err(io::Error::from)?;
+    replay
+        .config
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(replay)
+}
+
+/// Writes a replay to `path`, creating its directory if needed.
+pub fn save_replay(path: &Path, replay: &Replay) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(&SavedReplayRef {
+        version: VERSION,
+        replay,
+    })?;
+    fs::write(path, json)
+}
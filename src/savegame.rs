@@ -0,0 +1,91 @@
+// A match can be saved halfway through and picked up again later. The save is a
+// JSON file holding the whole `GameState`, including the state of its RNG, so
+// the match goes on exactly as it would have without the break. Whenever the
+// game state changes shape, `VERSION` goes up, and saves written by another
+// version are refused rather than loaded into the wrong places.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+// The version of the save format this build writes and understands.
+const VERSION: u32 = 2;
+
+/// The file as it is written.
+#[derive(Serialize)]
+struct SavedGameRef<'a> {
+    version: u32,
+    state: &'a GameState,
+}
+
+/// The version of a file we read, before we know whether we can read the rest.
+#[derive(Deserialize)]
+struct SavedVersion {
+    version: u32,
+}
+
+/// The file as it is read back.
+#[derive(Deserialize)]
+struct SavedGame {
+    state: GameState,
+}
+
+/// Where a match is saved unless told otherwise, which is `saved_match.json` in
+/// the user data directory. There might not be one on exotic systems, in which
+/// case we return `None`.
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "moving_paddles")
+        .map(|dirs| dirs.data_dir().join("saved_match.json"))
+}
+
+/// Writes `state` to `path`, creating its directory if needed.
+pub fn save_game(path: &Path, state: &GameState) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(&SavedGameRef {
+        version: VERSION,
+        state,
+    })?;
+    fs::write(path, json)
+}
+
+/// Reads a saved game from `path`. A save from another version of the game is
+/// an error, just like a file that isn't a save at all or one that has balls
+/// or padles off the board.
+pub fn load_game(path: &Path) -> io::Result<GameState> {
+    let json = fs::read_to_string(path)?;
+    let SavedVersion { version } = serde_json::from_str(&json).map_err(io::Error::from)?;
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the save is from version {} of the format, not {}", version, VERSION),
+        ));
+    }
+    let saved: SavedGame = serde_json::from_str(&json).map_err(io::Error::from)?;
+    saved
+        .state
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(saved.state)
+}
+
+/// `Rand32` can't be saved as it is, so a saved game holds the two numbers its
+/// state is made of instead.
+pub(crate) mod rng_state {
+    use oorandom::Rand32;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(rng: &Rand32, serializer: S) -> Result<S::Ok, S::Error> {
+        rng.state().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rand32, D::Error> {
+        Ok(Rand32::from_state(Deserialize::deserialize(deserializer)?))
+    }
+}
@@ -0,0 +1,63 @@
+// Short sound effects for the things that happen to the ball. They are loaded
+// from the resources directory, and any file that is missing or can't be
+// decoded just leaves that effect silent. Playing them needs the window, so
+// only `Sound` is there without the `gui` feature.
+
+#[cfg(feature = "gui")]
+use ggez::{
+    audio::{self, SoundSource},
+    Context,
+};
+use serde::{Deserialize, Serialize};
+
+/// The effects the game can play.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sound {
+    PaddleHit,
+    WallBounce,
+    Score,
+}
+
+#[cfg(feature = "gui")]
+#[derive(Default)]
+pub struct Sounds {
+    paddle_hit: Option<audio::Source>,
+    wall_bounce: Option<audio::Source>,
+    score: Option<audio::Source>,
+}
+
+#[cfg(feature = "gui")]
+impl Sounds {
+    /// Loads every effect that is available.
+    pub fn load(ctx: &Context) -> Self {
+        Sounds {
+            paddle_hit: load_source(ctx, "/paddle_hit.wav"),
+            wall_bounce: load_source(ctx, "/wall_bounce.wav"),
+            score: load_source(ctx, "/score.wav"),
+        }
+    }
+
+    /// Plays `sound` at `volume`, from 0 for silent to 1 for full volume, if it
+    /// was loaded. A sound that fails to play is not worth stopping the game for,
+    /// so we only report it.
+    pub fn play(&mut self, ctx: &Context, sound: Sound, volume: f32) {
+        let source = match sound {
+            Sound::PaddleHit => &mut self.paddle_hit,
+            Sound::WallBounce => &mut self.wall_bounce,
+            Sound::Score => &mut self.score,
+        };
+        if let Some(source) = source {
+            source.set_volume(volume);
+            if let Err(e) = source.play_detached(ctx) {
+                eprintln!("Could not play {:?}: {}", sound, e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+fn load_source(ctx: &Context, path: &str) -> Option<audio::Source> {
+    audio::Source::new(ctx, path)
+        .map_err(|e| eprintln!("Could not load sound {}: {}", path, e))
+        .ok()
+}
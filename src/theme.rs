@@ -0,0 +1,71 @@
+// A `Theme` groups all the colors the board is drawn in, so the look of the game
+// can be changed in one go. The players cycle through the presets with T.
+
+/// The colors the board is drawn in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// What the players see the theme called
+    pub name: &'static str,
+    pub background: [f32; 4],
+    /// Each player's own color, player 1's first. Their padle fades from it at
+    /// the bottom to `padle_top` at the top, and their score is drawn in it.
+    pub players: [[f32; 4]; 2],
+    pub padle_top: [f32; 4],
+    pub ball: [f32; 4],
+    /// The dashed net down the middle, which should stay in the background
+    pub net: [f32; 4],
+    pub obstacle: [f32; 4],
+    /// The frame drawn around the ball and padles when outlines are on
+    pub outline: [f32; 4],
+}
+
+/// The presets, starting with the one the game opens with.
+pub const THEMES: [Theme; 4] = [
+    // Cyan and magenta padles and a blue ball on black
+    Theme {
+        name: "Classic",
+        background: [0.0, 0.0, 0.0, 1.0],
+        players: [[0.3, 0.9, 1.0, 1.0], [1.0, 0.35, 0.85, 1.0]],
+        padle_top: [1.0, 1.0, 1.0, 1.0],
+        ball: [0.0, 0.0, 1.0, 1.0],
+        net: [0.4, 0.4, 0.4, 1.0],
+        obstacle: [0.6, 0.35, 0.2, 1.0],
+        outline: [1.0, 1.0, 1.0, 1.0],
+    },
+    // Glowing pink and cyan on a deep purple
+    Theme {
+        name: "Neon",
+        background: [0.05, 0.0, 0.1, 1.0],
+        players: [[1.0, 0.1, 0.8, 1.0], [0.2, 1.0, 1.0, 1.0]],
+        padle_top: [0.95, 0.9, 1.0, 1.0],
+        ball: [1.0, 1.0, 0.2, 1.0],
+        net: [0.4, 0.1, 0.5, 1.0],
+        obstacle: [0.2, 0.8, 0.3, 1.0],
+        outline: [1.0, 1.0, 1.0, 1.0],
+    },
+    // Shades of grey only
+    Theme {
+        name: "Mono",
+        background: [0.1, 0.1, 0.1, 1.0],
+        players: [[0.95, 0.95, 0.95, 1.0], [0.65, 0.65, 0.65, 1.0]],
+        padle_top: [0.45, 0.45, 0.45, 1.0],
+        ball: [1.0, 1.0, 1.0, 1.0],
+        net: [0.35, 0.35, 0.35, 1.0],
+        obstacle: [0.5, 0.5, 0.5, 1.0],
+        outline: [1.0, 1.0, 1.0, 1.0],
+    },
+    // Colors that people with the common kinds of color blindness can still tell
+    // apart, from the Okabe-Ito palette, and which differ in brightness as well:
+    // a bright orange ball, sky blue and reddish purple padles fading to white,
+    // and dark obstacles
+    Theme {
+        name: "High contrast",
+        background: [0.0, 0.0, 0.0, 1.0],
+        players: [[0.34, 0.71, 0.91, 1.0], [0.8, 0.47, 0.65, 1.0]],
+        padle_top: [1.0, 1.0, 1.0, 1.0],
+        ball: [0.9, 0.62, 0.0, 1.0],
+        net: [0.3, 0.3, 0.3, 1.0],
+        obstacle: [0.0, 0.45, 0.7, 1.0],
+        outline: [1.0, 1.0, 0.0, 1.0],
+    },
+];